@@ -1,9 +1,10 @@
 use std::collections::HashSet;
 
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::map::TileType;
-use crate::resources::CallbackEvent;
+use crate::resources::{AutoMoveKind, CallbackEvent};
 
 /// Marker struct indicating this entity is the player camera (so the camera should center on it)
 #[derive(Component, Copy, Clone, Eq, PartialEq, Hash)]
@@ -15,12 +16,38 @@ pub struct FpsTextBox;
 #[derive(Component)]
 pub struct LogsTextBox;
 
+#[derive(Component)]
+pub struct InventoryTextBox;
+
+/// Marker for the root node of a full screen menu (main menu, pause, game over)
+#[derive(Component)]
+pub struct MenuScreen;
+
+/// Marker for the highlight showing where the player is aiming
+#[derive(Component)]
+pub struct TargetCursor;
+
+/// Marker for every UI node making up the inventory panel, so it can be shown and hidden together
+#[derive(Component)]
+pub struct InventoryPanel;
+
+/// Marker for every UI node making up the quest journal, so it can be shown and hidden together
+#[derive(Component)]
+pub struct JournalPanel;
+
+#[derive(Component)]
+pub struct JournalTextBox;
+
+/// Marker for every UI node making up the conversation panel; it's only shown mid-conversation
+#[derive(Component)]
+pub struct DialoguePanel;
+
 /// Marker struct that this entity is the player
 #[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct Player;
 
 /// Position in the world (as opposed to a Bevy graphical transform)
-#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct WorldPos {
     pub x: i32,
     pub y: i32,
@@ -30,6 +57,11 @@ impl WorldPos {
     pub fn dist(&self, other: WorldPos) -> i32 {
         (self.x - other.x).abs() + (self.y - other.y).abs()
     }
+
+    /// Distance when a diagonal step counts the same as any other
+    pub fn chebyshev_dist(&self, other: WorldPos) -> i32 {
+        (self.x - other.x).abs().max((self.y - other.y).abs())
+    }
 }
 
 impl std::fmt::Display for WorldPos {
@@ -44,17 +76,306 @@ impl std::fmt::Display for WorldPos {
 pub struct EndOfTurnTrigger;
 
 /// Anything that needs a name, I guess
-#[derive(Component)]
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
 pub struct EntityName(pub String);
 
+/// Status effects a creature puts on whoever it hits, as long as the hit does any damage
+#[derive(Component, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct InflictsOnHit(pub Vec<StatusEffect>);
+
 /// Marker struct that an entity should be managed by a Monster AI
 #[derive(Component)]
 pub struct MonsterAI;
 
+/// Someone the player can talk to rather than fight; bumping into them starts the named
+/// conversation from `dialogue.ron`
+#[derive(Component, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Talker {
+    pub dialogue: String,
+}
+
+/// Which side an entity is on; how factions feel about each other is in `factions.ron`
+#[derive(Component, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Faction(pub String);
+
+/// How one entity personally feels about the player, on top of whatever their faction thinks.
+/// Zero is indifferent; strong enough feelings either way override the faction entirely.
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct Regard(pub i32);
+
+/// How someone (or some faction) is disposed toward someone else
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum Reaction {
+    Hostile,
+    Neutral,
+    Friendly,
+}
+
+/// The numbers in `CombatStats`, for dialogue (and quests, and endings) to check against
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum Stat {
+    Hp,
+    MaxHp,
+    Defense,
+    Power,
+}
+
+impl Stat {
+    pub fn of(self, stats: &CombatStats) -> i32 {
+        match self {
+            Stat::Hp => stats.hp,
+            Stat::MaxHp => stats.max_hp,
+            Stat::Defense => stats.defense,
+            Stat::Power => stats.power,
+        }
+    }
+}
+
+/// Something that has to be true of the player or the story so far; these decide which dialogue
+/// choices are offered, when quests start and move along, and how the game ends
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum StoryCondition {
+    StatAtLeast {
+        stat: Stat,
+        value: i32,
+    },
+    StatBelow {
+        stat: Stat,
+        value: i32,
+    },
+    HasFlag(String),
+    LacksFlag(String),
+    /// A story flag has been counted up to at least this much; a set flag counts as 1
+    CounterAtLeast {
+        counter: String,
+        value: i32,
+    },
+    /// At least this many of the named item, counting every stack
+    HasItem {
+        name: String,
+        count: u32,
+    },
+    LacksItem(String),
+    /// The player is on this depth or deeper
+    DepthAtLeast(u32),
+    /// The player's standing with a faction
+    StandingAtLeast {
+        faction: String,
+        value: i32,
+    },
+    StandingBelow {
+        faction: String,
+        value: i32,
+    },
+}
+
+/// Something that has to happen for a quest stage to be finished. Unlike `StoryCondition`s, the
+/// ones about things happening only count what happens after the stage starts.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum QuestCondition {
+    /// Kill this many monsters whose names start with `name` (so "Stone golem" counts
+    /// "Stone golem #3"); an empty name counts anything
+    Kill { name: String, count: u32 },
+    /// Lay eyes on a landmark with this name
+    SeeLandmark(String),
+    /// Just has to be true at some point
+    Holds(StoryCondition),
+}
+
+/// What picking a dialogue choice does. Each one is sent out as an event and handled by whatever
+/// system normally deals with that sort of thing.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum DialogueOutcome {
+    /// Goes in the log
+    Say(String),
+    SetFlag(String),
+    ClearFlag(String),
+    /// Adds to a story flag as a counter; the amount can be negative
+    AddToCounter {
+        counter: String,
+        amount: i32,
+    },
+    /// The item is made from its definition in `items.ron`
+    GiveItem {
+        name: String,
+        count: u32,
+    },
+    TakeItem {
+        name: String,
+        count: u32,
+    },
+    Heal(i32),
+    /// Whoever the player is talking to thinks this much better (or worse) of them
+    ChangeRegard(i32),
+    /// A whole faction thinks this much better (or worse) of the player
+    ChangeStanding {
+        faction: String,
+        amount: i32,
+    },
+    /// Whoever the player is talking to stops talking and attacks
+    StartFight,
+}
+
+/// What a monster knows about where the player is. Only the monster AI changes it, but anything
+/// is welcome to look.
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Awareness {
+    /// Hasn't seen the player, or has given up on them; just wanders around
+    Idle,
+    /// Can see the player, or is on the way to where they were last seen
+    Hunting {
+        last_seen: WorldPos,
+        seen_turn: usize,
+    },
+    /// Got to where the player was last seen and they weren't there; looks around for a while
+    Searching {
+        last_seen: WorldPos,
+        seen_turn: usize,
+        turns_left: u32,
+    },
+}
+
+impl Default for Awareness {
+    fn default() -> Self {
+        Awareness::Idle
+    }
+}
+
+impl Awareness {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Awareness::Idle => "idle",
+            Awareness::Hunting { .. } => "hunting",
+            Awareness::Searching { .. } => "searching",
+        }
+    }
+}
+
+/// One thing a monster cares about. Every turn each of a monster's behaviors suggests something
+/// to do (or nothing), and the most pressing suggestion wins.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Behavior {
+    /// Go after the player: attack when adjacent, otherwise head for wherever they were last seen
+    Chase,
+    /// Run from the player once health drops below this fraction of the maximum, and toward
+    /// friends if `toward_allies` is more than zero; that's how much getting closer to them
+    /// counts, next to getting away
+    FleeWhenHurt {
+        below: f32,
+        #[serde(default)]
+        toward_allies: f32,
+    },
+    /// Hang back from the player at about this many tiles
+    KeepDistance { range: i32 },
+    /// Never stray more than this many tiles from where the monster started out
+    Guard { radius: i32 },
+    /// On spotting the player, alert every monster within this many tiles that counts it a friend
+    CallAllies { radius: i32 },
+    /// With nothing better to do, shuffle around with this chance each turn
+    Wander { chance: f32 },
+}
+
+/// How a monster makes up its mind; see `Behavior`
+#[derive(Component, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AiBehaviors {
+    pub behaviors: Vec<Behavior>,
+    /// Where the monster started out, for guards
+    pub home: WorldPos,
+}
+
 /// Indicator that an entity prevents movement. Affects pathing.
 #[derive(Component)]
 pub struct BlocksMovement;
 
+/// How an entity should be drawn, if anything is drawing it. The simulation only deals in these;
+/// the actual sprites are attached later, so the game can run without a texture atlas (or a window).
+#[derive(Component, Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Renderable {
+    /// Index into the basic tiles sheet
+    pub sprite_index: usize,
+    pub color: Color,
+    /// z coordinate of the eventual transform; bigger is drawn on top
+    pub layer: f32,
+}
+
+/// Something that can be picked up and carried around. Items lying around have a `WorldPos`; items
+/// in someone's `Inventory` don't.
+#[derive(Component, Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Item {
+    /// Weight of a single one of these
+    pub weight: f32,
+    /// How many are in this stack
+    pub count: u32,
+}
+
+/// An item which does something when used, and is used up by it (one from the stack at a time)
+#[derive(Component, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Consumable {
+    pub targeting: Targeting,
+    pub effects: Vec<ItemEffect>,
+}
+
+/// Who gets the effects of a consumable
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum Targeting {
+    /// Just whoever used it
+    User,
+    /// One creature, at most `range` tiles away
+    Single { range: i32 },
+    /// Every creature within `radius` of a spot at most `range` tiles away
+    Area { range: i32, radius: i32 },
+}
+
+/// Something that happens to each target of a consumable
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum ItemEffect {
+    /// Restore this much health, but not past the maximum
+    Heal(i32),
+    Damage(i32),
+    /// Put a status effect on them; see `StatusKind`
+    Inflict(StatusEffect),
+}
+
+/// Where on the body a piece of equipment goes; only one thing fits in each
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum EquipmentSlot {
+    MainHand,
+    OffHand,
+    Body,
+    Head,
+}
+
+impl EquipmentSlot {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            EquipmentSlot::MainHand => "main hand",
+            EquipmentSlot::OffHand => "off hand",
+            EquipmentSlot::Body => "body",
+            EquipmentSlot::Head => "head",
+        }
+    }
+}
+
+/// An item which can be worn or wielded, and what it does for whoever does so
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Equippable {
+    pub slot: EquipmentSlot,
+    #[serde(default)]
+    pub power_bonus: i32,
+    #[serde(default)]
+    pub defense_bonus: i32,
+}
+
+/// Marker struct that an item is being worn or wielded by whoever has it in their inventory
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Equipped;
+
+/// Everything an entity is carrying, in the order it was picked up
+#[derive(Component, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Inventory {
+    pub items: Vec<Entity>,
+}
+
 /// Marker struct that an entity is a visual representation of a tile
 #[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct VisualTile(pub TileType);
@@ -64,6 +385,23 @@ pub struct VisualTile(pub TileType);
 #[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct RequiresSeen;
 
+/// Marker struct that the player has laid eyes on this entity at least once; see `EntityFirstSeen`
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Seen;
+
+/// Marker struct that the player has bumped into or stepped on this entity at least once; see
+/// `EntityFirstTouched`
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Touched;
+
+/// A part of the level worth remarking on, like a vault. It doesn't have a position of its own;
+/// it counts as seen as soon as any of its tiles are.
+#[derive(Component, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Landmark {
+    pub description: String,
+    pub tiles: Vec<WorldPos>,
+}
+
 /// Component describing a Viewshed, literally the set of tiles that are visible
 #[derive(Component, Clone, Eq, PartialEq, Debug)]
 pub struct Viewshed {
@@ -94,7 +432,7 @@ pub struct VisibilityChangedEvent;
 
 impl CallbackEvent for VisibilityChangedEvent {}
 
-#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct CombatStats {
     pub max_hp: i32,
     pub hp: i32,
@@ -107,6 +445,182 @@ pub struct CombatStats {
 #[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
 pub struct WantsTurnOrderAssignment;
 
+/// How quickly something acts. Everything builds up energy at its speed and spends it on
+/// actions (see `ActionCost`), so something twice as fast gets twice as many turns.
+/// Anything without one goes at normal speed.
+#[derive(Component, Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Speed(pub u32);
+
+impl Speed {
+    pub const NORMAL: Speed = Speed(100);
+
+    /// How long an action takes, in the time it takes a normal-speed entity to build up one
+    /// point of energy. Never zero, so nothing can act forever without anyone else going.
+    pub fn time_for(self, cost: ActionCost) -> u64 {
+        let time = cost.energy() as u64 * Speed::NORMAL.0 as u64 / self.0.max(1) as u64;
+        time.max(1)
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::NORMAL
+    }
+}
+
+/// Something lingering on a creature for a while, like poison. Effects wear off one turn at a time;
+/// see `StatusEffects`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub enum StatusKind {
+    /// Loses `potency` health every turn
+    Poisoned,
+    /// Loses `potency` health every turn
+    Bleeding,
+    /// Stumbles in a random direction about half the time
+    Confused,
+    /// Takes twice as long to do anything
+    Slowed,
+    /// Gets `potency` health back every turn
+    Regenerating,
+    /// Sees `potency` fewer tiles (but always at least the ones next door)
+    Blinded,
+}
+
+/// How a new dose of an effect combines with one that's already going
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Stacking {
+    /// The turns add up; the stronger of the two potencies stays
+    Duration,
+    /// The potencies add up; the longer of the two durations stays
+    Intensity,
+    /// Nothing adds up; the longer duration and the stronger potency stay
+    Refresh,
+}
+
+impl StatusKind {
+    /// How it reads in the logs, as in "Player is poisoned."
+    pub fn describe(&self) -> &'static str {
+        match self {
+            StatusKind::Poisoned => "poisoned",
+            StatusKind::Bleeding => "bleeding",
+            StatusKind::Confused => "confused",
+            StatusKind::Slowed => "slowed",
+            StatusKind::Regenerating => "regenerating",
+            StatusKind::Blinded => "blinded",
+        }
+    }
+
+    /// Whether putting this on someone is an attack on them
+    pub fn is_harmful(&self) -> bool {
+        !matches!(self, StatusKind::Regenerating)
+    }
+
+    pub fn stacking(&self) -> Stacking {
+        match self {
+            StatusKind::Poisoned => Stacking::Duration,
+            StatusKind::Bleeding => Stacking::Intensity,
+            StatusKind::Confused
+            | StatusKind::Slowed
+            | StatusKind::Regenerating
+            | StatusKind::Blinded => Stacking::Refresh,
+        }
+    }
+}
+
+/// One status effect, and how much longer it has to go
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    pub turns: u32,
+    /// What this means depends on the kind; some kinds don't use it at all
+    #[serde(default)]
+    pub potency: i32,
+    /// Whoever put it there, if anyone, so whatever it does can be traced back to them. This
+    /// isn't saved; after a load, anything still lingering is nobody's doing.
+    #[serde(skip)]
+    pub source: Option<Entity>,
+}
+
+impl StatusEffect {
+    /// This effect with another dose of the same kind on top, following the kind's `Stacking`
+    pub fn stacked_with(self, other: StatusEffect) -> StatusEffect {
+        let (turns, potency) = match self.kind.stacking() {
+            Stacking::Duration => (self.turns + other.turns, self.potency.max(other.potency)),
+            Stacking::Intensity => (self.turns.max(other.turns), self.potency + other.potency),
+            Stacking::Refresh => (self.turns.max(other.turns), self.potency.max(other.potency)),
+        };
+        StatusEffect {
+            kind: self.kind,
+            turns,
+            potency,
+            // the latest dose is the one anything from here on gets blamed on
+            source: other.source.or(self.source),
+        }
+    }
+}
+
+/// Every status effect currently on a creature; at most one of each kind, since new doses stack
+/// onto the old ones. Anything that can have effects put on it starts out with an empty one.
+/// Effects with no turns left have worn off, and only stick around until the next turn's tick.
+#[derive(Component, Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct StatusEffects(pub Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub fn get(&self, kind: StatusKind) -> Option<&StatusEffect> {
+        self.0.iter().find(|e| e.kind == kind && e.turns > 0)
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.get(kind).is_some()
+    }
+
+    /// How fast someone with these effects actually goes
+    pub fn speed(&self, base: Speed) -> Speed {
+        if self.has(StatusKind::Slowed) {
+            Speed((base.0 / 2).max(1))
+        } else {
+            base
+        }
+    }
+
+    /// How far someone with these effects can actually see
+    pub fn sight_range(&self, base: i32) -> i32 {
+        match self.get(StatusKind::Blinded) {
+            Some(blind) => (base - blind.potency).max(1),
+            None => base,
+        }
+    }
+}
+
+/// What an entity did with its turn, which decides how long until it gets another one
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ActionCost {
+    /// Also what the turn counter does, so a round lasts as long as a normal-speed wait
+    Wait,
+    Move,
+    Attack,
+    Stairs,
+    PickUp,
+    Drop,
+    UseItem,
+    Equip,
+}
+
+impl ActionCost {
+    pub fn energy(self) -> u32 {
+        match self {
+            ActionCost::Wait => 100,
+            ActionCost::Move => 100,
+            ActionCost::Attack => 100,
+            ActionCost::Stairs => 100,
+            ActionCost::PickUp => 50,
+            ActionCost::Drop => 50,
+            ActionCost::UseItem => 150,
+            ActionCost::Equip => 200,
+        }
+    }
+}
+
 /// Marker struct that an entity wants to be part of the turn order.
 /// Change detection will find these things and give them spots.
 #[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
@@ -116,6 +630,7 @@ pub struct WantsMapIndexing;
 #[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
 pub struct EntityFinishedTurn {
     pub entity: Entity,
+    pub cost: ActionCost,
 }
 
 impl CallbackEvent for EntityFinishedTurn {}
@@ -134,6 +649,8 @@ impl CallbackEvent for EntityMeleeAttacks {}
 pub struct EntitySuffersDamage {
     pub entity: Entity,
     pub damage: i32,
+    /// Whoever (or whatever) did it, if anyone
+    pub source: Option<Entity>,
 }
 
 impl CallbackEvent for EntitySuffersDamage {}
@@ -152,6 +669,9 @@ impl CallbackEvent for EntityMovedEvent {}
 #[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
 pub struct EntityDies {
     pub entity: Entity,
+    /// Whoever (or whatever) did the last of the damage, if anyone; damage from an item or a
+    /// lingering effect is down to whoever used or inflicted it
+    pub killer: Option<Entity>,
 }
 
 impl CallbackEvent for EntityDies {}
@@ -162,3 +682,174 @@ pub struct LogIssuedEvent {
 }
 
 impl CallbackEvent for LogIssuedEvent {}
+
+/// Event indicating an entity is picking up an item from the floor
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ItemPickedUp {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+impl CallbackEvent for ItemPickedUp {}
+
+/// Event indicating an entity is dropping an item it carries at its feet
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ItemDropped {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+impl CallbackEvent for ItemDropped {}
+
+/// Event indicating an entity is using (and using up) one of the items it carries
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityUsesItem {
+    pub entity: Entity,
+    pub item: Entity,
+    /// Where to aim it, for items which need aiming; if not given, the closest creature in view
+    pub target: Option<WorldPos>,
+}
+
+impl CallbackEvent for EntityUsesItem {}
+
+/// Event indicating the player wants to keep doing something on their own, a step per turn
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PlayerStartsAutoMove {
+    pub kind: AutoMoveKind,
+}
+
+impl CallbackEvent for PlayerStartsAutoMove {}
+
+/// Event indicating the player has bumped into someone to talk to
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PlayerStartsDialogue {
+    pub speaker: Entity,
+}
+
+impl CallbackEvent for PlayerStartsDialogue {}
+
+/// Event indicating the player has seen something (or somewhere) for the very first time
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityFirstSeen {
+    pub entity: Entity,
+}
+
+impl CallbackEvent for EntityFirstSeen {}
+
+/// Event indicating the player has bumped into or stepped on something for the very first time
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityFirstTouched {
+    pub entity: Entity,
+}
+
+impl CallbackEvent for EntityFirstTouched {}
+
+/// A story flag is being set (or cleared, if `set` is false)
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
+pub struct StoryFlagChanged {
+    pub flag: String,
+    pub set: bool,
+}
+
+impl CallbackEvent for StoryFlagChanged {}
+
+/// A story flag is being counted up (or down)
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
+pub struct StoryCounterChanged {
+    pub counter: String,
+    pub amount: i32,
+}
+
+impl CallbackEvent for StoryCounterChanged {}
+
+/// Entity gets some brand new items, by name; see `items.ron`
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
+pub struct EntityGivenItem {
+    pub entity: Entity,
+    pub name: String,
+    pub count: u32,
+}
+
+impl CallbackEvent for EntityGivenItem {}
+
+/// Entity gives up some of what it's carrying, by name; they're gone for good
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
+pub struct EntityLosesItem {
+    pub entity: Entity,
+    pub name: String,
+    pub count: u32,
+}
+
+impl CallbackEvent for EntityLosesItem {}
+
+/// Entity thinks better (or worse) of the player
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityRegardChanged {
+    pub entity: Entity,
+    pub amount: i32,
+}
+
+impl CallbackEvent for EntityRegardChanged {}
+
+/// A whole faction thinks better (or worse) of the player
+#[derive(Component, Clone, Eq, PartialEq, Debug)]
+pub struct FactionStandingChanged {
+    pub faction: String,
+    pub amount: i32,
+}
+
+impl CallbackEvent for FactionStandingChanged {}
+
+/// Entity stops being friendly and starts fighting the player
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityTurnsHostile {
+    pub entity: Entity,
+}
+
+impl CallbackEvent for EntityTurnsHostile {}
+
+/// Entity is getting a (possibly additional) dose of a status effect
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityGainsStatus {
+    pub entity: Entity,
+    pub effect: StatusEffect,
+    /// Whoever (or whatever) did it, if anyone
+    pub source: Option<Entity>,
+}
+
+impl CallbackEvent for EntityGainsStatus {}
+
+/// Entity's status effects changed, by something being put on it or by something wearing off
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityStatusChanged {
+    pub entity: Entity,
+}
+
+impl CallbackEvent for EntityStatusChanged {}
+
+/// Entity is recovering some health
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityHealed {
+    pub entity: Entity,
+    pub amount: i32,
+}
+
+impl CallbackEvent for EntityHealed {}
+
+/// Event indicating an entity is putting on (or wielding) an item it carries
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityEquipsItem {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+impl CallbackEvent for EntityEquipsItem {}
+
+/// Event indicating an entity is taking off (or putting away) an item it has equipped
+#[derive(Component, Copy, Clone, Eq, PartialEq, Debug)]
+pub struct EntityUnequipsItem {
+    pub entity: Entity,
+    pub item: Entity,
+}
+
+impl CallbackEvent for EntityUnequipsItem {}
@@ -0,0 +1,61 @@
+//! Systems for running the game with nobody watching; see `HeadlessPlugin`.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::components::*;
+use crate::resources::*;
+
+/// Stand-in for the keyboard; mashes a random direction every frame. It has nothing to say to
+/// anybody, so it walks away from every conversation.
+pub fn random_walk_input(
+    mut input_state: ResMut<PlayerInputState>,
+    dialogue: Res<ActiveDialogue>,
+    mut rng: ResMut<GameRng>,
+) {
+    *input_state = PlayerInputState::default();
+
+    if dialogue.0.is_some() {
+        input_state.cancel_pressed = true;
+        return;
+    }
+
+    match rng.0.gen_range(0..5) {
+        0 => input_state.up_pressed = true,
+        1 => input_state.down_pressed = true,
+        2 => input_state.left_pressed = true,
+        3 => input_state.right_pressed = true,
+        4 => input_state.pass_pressed = true,
+        _ => unreachable!(),
+    }
+}
+
+/// Quit once the turn limit is reached, once there's no player left to play, or once the game
+/// has ended some other way (nobody is around to dismiss the game over screen).
+pub fn stop_simulation(
+    turn: Res<CurrentTurnNumber>,
+    limit: Res<TurnLimit>,
+    seed: Res<GameSeed>,
+    player_query: Query<(), With<Player>>,
+    info: Option<Res<GameOverInfo>>,
+    state: Res<State<GameState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let player_alive = player_query.iter().next().is_some();
+    let game_over = info.is_some() || *state.current() == GameState::GameOver;
+
+    if turn.0 >= limit.0 || !player_alive || game_over {
+        println!(
+            "Simulation stopped after {} turns (seed: {}, player alive: {})",
+            turn.0, seed.0, player_alive
+        );
+        if let Some(info) = info {
+            match &info.ending {
+                Some(ending) => println!("Reached ending: {}", ending),
+                None => println!("Player died: {}", info.cause),
+            }
+        }
+        exit.send(AppExit);
+    }
+}
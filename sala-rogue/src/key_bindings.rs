@@ -0,0 +1,257 @@
+//! Which keys do what. Every action the player can take from the keyboard has a list of keys
+//! bound to it; the defaults live here, and anything in the key bindings file overrides them.
+//! The file is optional, and is written back out whenever keys are rebound from the pause menu.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Everything a key can be bound to
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+pub enum InputAction {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    MoveUpLeft,
+    MoveUpRight,
+    MoveDownLeft,
+    MoveDownRight,
+    Wait,
+    Descend,
+    Ascend,
+    Pickup,
+    Confirm,
+    Cancel,
+    Travel,
+    Explore,
+    ToggleInventory,
+    ToggleJournal,
+    Pause,
+    QuickSave,
+    QuickLoad,
+    ToggleFps,
+}
+
+impl InputAction {
+    /// In the order they're listed on the key bindings screen
+    pub const ALL: [InputAction; 22] = [
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveUpLeft,
+        InputAction::MoveUpRight,
+        InputAction::MoveDownLeft,
+        InputAction::MoveDownRight,
+        InputAction::Wait,
+        InputAction::Descend,
+        InputAction::Ascend,
+        InputAction::Pickup,
+        InputAction::Confirm,
+        InputAction::Cancel,
+        InputAction::Travel,
+        InputAction::Explore,
+        InputAction::ToggleInventory,
+        InputAction::ToggleJournal,
+        InputAction::Pause,
+        InputAction::QuickSave,
+        InputAction::QuickLoad,
+        InputAction::ToggleFps,
+    ];
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            InputAction::MoveLeft => "Move left",
+            InputAction::MoveRight => "Move right",
+            InputAction::MoveUp => "Move up",
+            InputAction::MoveDown => "Move down",
+            InputAction::MoveUpLeft => "Move up and left",
+            InputAction::MoveUpRight => "Move up and right",
+            InputAction::MoveDownLeft => "Move down and left",
+            InputAction::MoveDownRight => "Move down and right",
+            InputAction::Wait => "Wait a turn",
+            InputAction::Descend => "Go downstairs",
+            InputAction::Ascend => "Go upstairs",
+            InputAction::Pickup => "Pick up",
+            InputAction::Confirm => "Confirm target",
+            InputAction::Cancel => "Cancel target",
+            InputAction::Travel => "Travel",
+            InputAction::Explore => "Explore",
+            InputAction::ToggleInventory => "Show inventory",
+            InputAction::ToggleJournal => "Show journal",
+            InputAction::Pause => "Pause",
+            InputAction::QuickSave => "Save",
+            InputAction::QuickLoad => "Load",
+            InputAction::ToggleFps => "Show FPS",
+        }
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        match self {
+            InputAction::MoveLeft => vec![KeyCode::A, KeyCode::Left, KeyCode::Numpad4],
+            InputAction::MoveRight => vec![KeyCode::D, KeyCode::Right, KeyCode::Numpad6],
+            InputAction::MoveUp => vec![KeyCode::W, KeyCode::Up, KeyCode::Numpad8],
+            InputAction::MoveDown => vec![KeyCode::S, KeyCode::Down, KeyCode::Numpad2],
+            // numpad and vi-style; with num lock off, the numpad sends these other keys instead
+            InputAction::MoveUpLeft => vec![KeyCode::Y, KeyCode::Numpad7, KeyCode::Home],
+            InputAction::MoveUpRight => vec![KeyCode::U, KeyCode::Numpad9, KeyCode::PageUp],
+            InputAction::MoveDownLeft => vec![KeyCode::B, KeyCode::Numpad1, KeyCode::End],
+            InputAction::MoveDownRight => vec![KeyCode::N, KeyCode::Numpad3, KeyCode::PageDown],
+            InputAction::Wait => vec![KeyCode::Space, KeyCode::Numpad5],
+            // the '>' and '<' keys, shift or no shift
+            InputAction::Descend => vec![KeyCode::Period],
+            InputAction::Ascend => vec![KeyCode::Comma],
+            InputAction::Pickup => vec![KeyCode::G],
+            InputAction::Confirm => vec![KeyCode::Return, KeyCode::NumpadEnter],
+            InputAction::Cancel => vec![KeyCode::Back],
+            InputAction::Travel => vec![KeyCode::T],
+            InputAction::Explore => vec![KeyCode::X],
+            InputAction::ToggleInventory => vec![KeyCode::I],
+            InputAction::ToggleJournal => vec![KeyCode::J],
+            InputAction::Pause => vec![KeyCode::Escape],
+            InputAction::QuickSave => vec![KeyCode::F5],
+            InputAction::QuickLoad => vec![KeyCode::F9],
+            InputAction::ToggleFps => vec![KeyCode::F],
+        }
+    }
+}
+
+/// Where the key bindings are read from at startup, and written to when they change
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct KeyBindingsFile(pub PathBuf);
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct KeyBindings {
+    bindings: BTreeMap<InputAction, Vec<KeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            bindings: InputAction::ALL
+                .iter()
+                .map(|action| (*action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: InputAction) -> &[KeyCode] {
+        self.bindings
+            .get(&action)
+            .map(|keys| keys.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn just_pressed(&self, kb_input: &Input<KeyCode>, action: InputAction) -> bool {
+        kb_input.any_just_pressed(self.keys(action).iter().copied())
+    }
+
+    /// Like `just_pressed`, but the press is used up, so nothing else later in the frame sees it
+    pub fn clear_just_pressed(&self, kb_input: &mut Input<KeyCode>, action: InputAction) -> bool {
+        self.keys(action)
+            .iter()
+            .fold(false, |acc, key| kb_input.clear_just_pressed(*key) || acc)
+    }
+
+    /// The keys for an action, for showing to the player
+    pub fn describe(&self, action: InputAction) -> String {
+        let keys = self.keys(action);
+        if keys.is_empty() {
+            "(unbound)".to_string()
+        } else {
+            keys.iter()
+                .map(|key| format!("{:?}", key))
+                .collect::<Vec<_>>()
+                .join(" / ")
+        }
+    }
+
+    /// Bind a key to an action, either alongside its other keys or replacing them. A key only
+    /// ever does one thing, so it's taken away from whatever had it before -- unless that would
+    /// leave the other action with no keys at all, in which case nothing changes.
+    pub fn bind(&mut self, action: InputAction, key: KeyCode, replace: bool) -> Result<(), String> {
+        let previous_owner = self
+            .bindings
+            .iter()
+            .find(|(other, keys)| **other != action && keys.contains(&key))
+            .map(|(other, _)| *other);
+
+        if let Some(other) = previous_owner {
+            if self.keys(other).len() == 1 {
+                return Err(format!(
+                    "{:?} is the only key for {}; rebind that first.",
+                    key,
+                    other.describe()
+                ));
+            }
+            self.bindings
+                .entry(other)
+                .or_default()
+                .retain(|k| *k != key);
+        }
+
+        let keys = self.bindings.entry(action).or_default();
+        if replace {
+            keys.clear();
+        }
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+
+        Ok(())
+    }
+
+    /// Go back to the default keys for an action, as far as possible; any default key that's
+    /// now the only key for something else stays where it is.
+    pub fn reset(&mut self, action: InputAction) -> Result<(), String> {
+        self.bindings.insert(action, Vec::new());
+
+        let mut result = Ok(());
+        for key in action.default_keys() {
+            if let Err(e) = self.bind(action, key, false) {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Read the bindings file over the top of the defaults, so it only needs to mention the
+    /// actions that were changed. A missing file just means the defaults; a broken one is
+    /// complained about and then ignored, since the game is still playable without it.
+    pub fn load(path: &Path) -> KeyBindings {
+        let mut out = KeyBindings::default();
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return out,
+        };
+
+        match ron::from_str::<BTreeMap<InputAction, Vec<KeyCode>>>(&text) {
+            Ok(from_file) => out.bindings.extend(from_file),
+            Err(e) => warn!(
+                "Could not parse key bindings file {}, using the defaults: {}",
+                path.display(),
+                e
+            ),
+        }
+
+        out
+    }
+
+    pub fn save(&self, path: &Path) {
+        let text = ron::ser::to_string_pretty(&self.bindings, ron::ser::PrettyConfig::default())
+            .expect("Key bindings should always serialize");
+        if let Err(e) = std::fs::write(path, text) {
+            warn!(
+                "Could not write key bindings file {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
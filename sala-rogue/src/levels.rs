@@ -0,0 +1,171 @@
+//! Moving between levels of the dungeon. The level being left is frozen (the same way it would be
+//! saved to a file) so it can be brought back exactly as it was if the player returns.
+
+use std::collections::{HashMap, HashSet};
+
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::map::Map;
+use crate::raws::{ItemRegistry, LevelStyleRegistry, MonsterRegistry, NpcRegistry};
+use crate::resources::*;
+use crate::running_systems::distance_dijkstra_map;
+use crate::save_load::{despawn_saved_entity, save_entities, spawn_saved_entity, SavedLevel};
+use crate::setup_systems::{arrival_point, generate_level};
+
+/// Every level the player has visited but isn't on right now, by depth
+#[derive(Default, Clone, Debug)]
+pub struct StashedLevels(pub HashMap<u32, SavedLevel>);
+
+/// Things that belong to a level, and stay behind when the player leaves it
+type LevelEntityFilter = (
+    Or<(With<WorldPos>, With<Landmark>)>,
+    Without<Player>,
+    Without<VisualTile>,
+);
+
+/// Swap the current level out for the next one up or down, building it if it's new.
+pub fn change_level(world: &mut World, direction: StairsDirection) {
+    let old_depth = world
+        .get_resource::<CurrentDepth>()
+        .expect("Depth should be set up")
+        .0;
+    let new_depth = match direction {
+        StairsDirection::Down => old_depth + 1,
+        StairsDirection::Up if old_depth > 1 => old_depth - 1,
+        // nowhere to go
+        StairsDirection::Up => return,
+    };
+
+    // freeze the level we're leaving
+    let entities = save_entities::<LevelEntityFilter>(world);
+    for (entity, _) in entities.iter() {
+        despawn_saved_entity(world, *entity);
+    }
+    let old_map = std::mem::take(
+        &mut *world
+            .get_resource_mut::<Map>()
+            .expect("Map should be set up"),
+    );
+    let stashed = SavedLevel {
+        depth: old_depth,
+        map: old_map.snapshot(),
+        entities: entities.into_iter().map(|(_, saved)| saved).collect(),
+    };
+
+    let mut stashed_levels = world.remove_resource::<StashedLevels>().unwrap_or_default();
+    stashed_levels.0.insert(old_depth, stashed);
+
+    // then bring back (or build) the one we're going to
+    let map = match stashed_levels.0.remove(&new_depth) {
+        Some(level) => {
+            for saved in level.entities {
+                let entity = spawn_saved_entity(world, saved);
+                if world.get::<MonsterAI>(entity).is_some() {
+                    world.entity_mut(entity).insert(WantsTurnOrderAssignment);
+                }
+            }
+            Map::from_snapshot(level.map)
+        }
+        None => {
+            let seed = world
+                .get_resource::<GameSeed>()
+                .expect("Seed should be set up")
+                .0;
+            let monsters = world
+                .get_resource::<MonsterRegistry>()
+                .expect("Raws should be loaded")
+                .clone();
+            let items = world
+                .get_resource::<ItemRegistry>()
+                .expect("Raws should be loaded")
+                .clone();
+            let npcs = world
+                .get_resource::<NpcRegistry>()
+                .expect("Raws should be loaded")
+                .clone();
+            let level_styles = world
+                .get_resource::<LevelStyleRegistry>()
+                .expect("Raws should be loaded")
+                .clone();
+
+            let mut queue = CommandQueue::default();
+            let mut commands = Commands::new(&mut queue, world);
+            let (map, _) = generate_level(
+                &mut commands,
+                &monsters,
+                &items,
+                &npcs,
+                &level_styles,
+                seed,
+                new_depth,
+            );
+            queue.apply(world);
+            map
+        }
+    };
+
+    world.insert_resource(stashed_levels);
+
+    // put the player on the stairs, or as close as we can get if someone is standing there
+    let occupied: HashSet<WorldPos> = world
+        .query_filtered::<&WorldPos, (With<BlocksMovement>, Without<Player>)>()
+        .iter(world)
+        .copied()
+        .collect();
+    let stairs = arrival_point(&map, direction);
+    let distances = distance_dijkstra_map(&map, [stairs].iter(), |_| false);
+    let player_pos = distances
+        .iter()
+        .filter(|(wp, _)| !occupied.contains(wp))
+        .min_by_key(|(wp, dist)| (**dist, wp.x, wp.y))
+        .map(|(wp, _)| *wp)
+        .unwrap_or(stairs);
+
+    let mut turn_order = TurnOrder::default();
+    let turn_counters: Vec<Entity> = world
+        .query_filtered::<Entity, With<EndOfTurnTrigger>>()
+        .iter(world)
+        .collect();
+    for entity in turn_counters {
+        turn_order.add_if_not_present(entity);
+    }
+
+    let players: Vec<Entity> = world
+        .query_filtered::<Entity, With<Player>>()
+        .iter(world)
+        .collect();
+    for entity in players {
+        turn_order.add_if_not_present(entity);
+
+        let mut player = world.entity_mut(entity);
+        player.insert(player_pos).insert(WantsMapIndexing);
+        if let Some(mut vs) = player.get_mut::<Viewshed>() {
+            // empty viewsheds get recomputed
+            vs.visible_tiles.clear();
+        }
+    }
+
+    world.insert_resource(map);
+    world.insert_resource(CurrentDepth(new_depth));
+    world.insert_resource(turn_order);
+    world.insert_resource(BlockedTiles::default());
+    world.insert_resource(CombatStatsTiles::default());
+    world.insert_resource(DijkstraMaps::default());
+    world.insert_resource(TargetingMode::default());
+    world.insert_resource(AutoMove::default());
+
+    let message = match direction {
+        StairsDirection::Down => format!("You descend to depth {}.", new_depth),
+        StairsDirection::Up => format!("You climb back up to depth {}.", new_depth),
+    };
+
+    let mut events = world
+        .get_resource_mut::<CallbackEvents>()
+        .expect("Events should be set up");
+    events.send(MapChangedEvent);
+    events.send(LogIssuedEvent {
+        log: Log { message },
+    });
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use bevy::ecs::schedule::IntoSystemDescriptor;
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 
@@ -6,12 +8,49 @@ mod bevy_util;
 pub(crate) mod components;
 pub(crate) mod resources;
 
+mod levels;
 mod map;
+mod map_builders;
+mod raws;
 
+mod headless_systems;
+mod key_bindings;
+mod menu_systems;
+mod presentation_systems;
 mod running_systems;
+mod save_load;
 mod setup_systems;
 
-struct MapPlugin;
+/// The rules of the game: turn order, AI, combat, FOV, logs. Needs no window or assets, so it can
+/// run on top of `MinimalPlugins`.
+struct SimulationPlugin {
+    /// Seed for every new game; each one gets a random seed if this is empty
+    seed: Option<u64>,
+    save_file: PathBuf,
+    /// Start from the save file instead of a fresh dungeon
+    load_on_start: bool,
+    /// The screen to start on; turns are only taken in `GameState::InGame`
+    initial_state: resources::GameState,
+    settings: resources::GameSettings,
+}
+
+/// Sprites, camera, UI and keyboard input; everything a human needs to play.
+struct PresentationPlugin {
+    /// Key bindings are read from here if it exists, and written here when they're changed
+    key_bindings_file: PathBuf,
+}
+
+/// Drives the simulation with a bot instead of a keyboard and stops after enough turns.
+struct HeadlessPlugin {
+    turn_limit: usize,
+}
+
+/// Stage (added by the simulation) where the player's input for the frame gets decided
+const PLAYER_INPUT: &str = "player input";
+
+/// Label for the system that presses keys for the player while they're traveling and so on.
+/// Whatever reads the real input has to go before it, so it can tell if the player cut in.
+const AUTO_MOVE_INPUT: &str = "auto move input";
 
 fn camera_setup(mut commands: Commands) {
     use components::*;
@@ -79,15 +118,38 @@ impl AppExtension for SystemStage {
     }
 }
 
-impl Plugin for MapPlugin {
+impl Plugin for SimulationPlugin {
     fn build(&self, app: &mut App) {
-        const ASSET_LOADING: &str = "load assets";
+        const RAWS_LOADING: &str = "load raws";
         const WORLD_SETUP: &str = "setup map";
 
         use map::*;
         use resources::*;
 
-        app.insert_resource(PlayerInputState::default())
+        let pending_load = if self.load_on_start {
+            Some(SaveLoadAction::Load)
+        } else {
+            None
+        };
+
+        let seed = self.seed.unwrap_or_else(rand::random);
+
+        app.insert_resource(GameSeed(seed))
+            .insert_resource(GameRng::from_seed(seed))
+            .insert_resource(FixedSeed(self.seed))
+            .insert_resource(self.settings)
+            .insert_resource(SaveFilePath(self.save_file.clone()))
+            .insert_resource(PendingSaveLoad(pending_load))
+            .insert_resource(CurrentDepth(1))
+            .insert_resource(PendingLevelChange::default())
+            .insert_resource(levels::StashedLevels::default())
+            .insert_resource(PlayerInputState::default())
+            .insert_resource(TargetingMode::default())
+            .insert_resource(AutoMove::default())
+            .insert_resource(StoryFlags::default())
+            .insert_resource(QuestLog::default())
+            .insert_resource(FactionStandings::default())
+            .insert_resource(ActiveDialogue::default())
             .insert_resource(Map::default())
             .insert_resource(Logs::default())
             .insert_resource(CurrentTurnNumber::default())
@@ -95,43 +157,277 @@ impl Plugin for MapPlugin {
             .insert_resource(PlayerNoAction::default())
             .insert_resource(BlockedTiles::default())
             .insert_resource(CombatStatsTiles::default())
-            .insert_resource(PlayerDistanceMap::default())
+            .insert_resource(DijkstraMaps::default())
             .insert_resource(TurnOrder::default())
             .insert_resource(CallbackEvents::default())
-            // asset loading
-            .add_startup_stage(ASSET_LOADING, SystemStage::single_threaded())
-            .add_startup_system_to_stage(ASSET_LOADING, setup_systems::load_tileset)
+            .add_state(self.initial_state)
+            // raws loading
+            .add_startup_stage(RAWS_LOADING, SystemStage::single_threaded())
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_monster_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_item_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_level_style_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_npc_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_dialogue_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_quest_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_ending_registry)
+            .add_startup_system_to_stage(RAWS_LOADING, raws::load_faction_registry)
             // setup systems
-            .add_startup_stage_after(ASSET_LOADING, WORLD_SETUP, SystemStage::single_threaded())
+            .add_startup_stage_after(RAWS_LOADING, WORLD_SETUP, SystemStage::single_threaded())
             .add_startup_system_to_stage(WORLD_SETUP, setup_systems::make_map)
-            .add_startup_system_to_stage(WORLD_SETUP, camera_setup)
             .add_startup_system_to_stage(WORLD_SETUP, setup_systems::setup_turn_counter)
+            // whoever is driving the player (a keyboard, a bot) fills in PlayerInputState here
+            .add_stage_before(
+                CoreStage::Update,
+                PLAYER_INPUT,
+                SystemStage::single_threaded(),
+            )
+            // only while playing, or a pause (which leaves the last keys pressed) would cancel it
+            .add_system_to_stage(
+                PLAYER_INPUT,
+                running_systems::auto_move_input
+                    .with_run_criteria(menu_systems::in_game)
+                    .label(AUTO_MOVE_INPUT),
+            )
+            // saves and loads happen after input is read, in between turns
+            .add_system_to_stage(
+                PLAYER_INPUT,
+                save_load::process_save_load.exclusive_system().at_end(),
+            )
+            // i guess this is sloppy use of bevy but damn it i want my callbacks to be processed in one frame
+            .add_system(
+                running_systems::world_tick
+                    .exclusive_system()
+                    .with_run_criteria(menu_systems::in_game),
+            );
+    }
+}
+
+impl Plugin for PresentationPlugin {
+    fn build(&self, app: &mut App) {
+        const ASSET_LOADING: &str = "load assets";
+        const REBUILD_GRAPHICS: &str = "rebuild graphics";
+
+        use key_bindings::{KeyBindings, KeyBindingsFile};
+        use resources::{GameState, KeyBindingsMenu};
+
+        app.insert_resource(KeyBindings::load(&self.key_bindings_file))
+            .insert_resource(KeyBindingsFile(self.key_bindings_file.clone()))
+            .insert_resource(KeyBindingsMenu::default())
+            // asset loading
+            .add_startup_stage(ASSET_LOADING, SystemStage::single_threaded())
+            .add_startup_system_to_stage(ASSET_LOADING, setup_systems::load_tileset)
+            .add_startup_system(camera_setup)
             .add_startup_system(setup_systems::setup_fps_tracker)
             .add_startup_system(setup_systems::setup_log_component)
+            .add_startup_system(setup_systems::setup_inventory_component)
+            .add_startup_system(setup_systems::setup_journal_component)
+            .add_startup_system(setup_systems::setup_dialogue_component)
             // input systems
-            // TODO: remove this once we have real UI around this
-            .add_system(bevy::input::system::exit_on_esc_system)
-            .add_system(running_systems::update_fps_text)
-            // i guess this is sloppy use of bevy but damn it i want my callbacks to be processed in one frame
-            .add_system(running_systems::world_tick.exclusive_system())
+            .add_system_set_to_stage(
+                PLAYER_INPUT,
+                SystemSet::new()
+                    .with_run_criteria(menu_systems::in_game)
+                    .before(AUTO_MOVE_INPUT)
+                    .with_system(presentation_systems::get_player_input.system())
+                    .with_system(presentation_systems::get_save_load_input.system()),
+            )
+            // menus
+            .add_system_set(
+                SystemSet::on_enter(GameState::MainMenu)
+                    .with_system(menu_systems::setup_main_menu.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::MainMenu)
+                    .with_system(menu_systems::main_menu_input.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::MainMenu)
+                    .with_system(menu_systems::teardown_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::InGame)
+                    .with_system(menu_systems::in_game_menu_input.system()),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::Paused)
+                    .with_system(menu_systems::setup_pause_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Paused)
+                    .with_system(menu_systems::pause_input.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Paused)
+                    .with_system(menu_systems::teardown_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::KeyBindings)
+                    .with_system(menu_systems::reset_key_bindings_menu.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::KeyBindings)
+                    .with_system(menu_systems::key_bindings_input.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::KeyBindings)
+                    .with_system(menu_systems::teardown_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::GameOver)
+                    .with_system(menu_systems::setup_game_over_screen.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::GameOver)
+                    .with_system(menu_systems::game_over_input.system()),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::GameOver)
+                    .with_system(menu_systems::teardown_screen.system()),
+            )
+            .add_system(presentation_systems::update_fps_text)
+            .add_system(presentation_systems::toggle_inventory_panel)
+            .add_system(presentation_systems::toggle_journal_panel)
+            .add_startup_system(presentation_systems::setup_targeting_cursor)
+            // runs after world_tick (exclusive systems go first), so new sprites are in place
+            // before the graphics are rebuilt
+            .add_system(presentation_systems::attach_sprites)
             .add_stage_after(
                 CoreStage::Update,
-                "rebuild graphics",
+                REBUILD_GRAPHICS,
                 SystemStage::single_threaded(),
             )
             .add_system_set_to_stage(
-                "rebuild graphics",
+                REBUILD_GRAPHICS,
                 SystemSet::new()
-                    .with_system(running_systems::aim_camera.system())
-                    .with_system(running_systems::hide_unseen_things.system())
-                    .with_system(running_systems::world_pos_to_visual_system.system()),
+                    .with_run_criteria(menu_systems::showing_game)
+                    .with_system(presentation_systems::aim_camera.system())
+                    .with_system(presentation_systems::hide_unseen_things.system())
+                    .with_system(presentation_systems::world_pos_to_visual_system.system())
+                    .with_system(presentation_systems::update_targeting_cursor.system())
+                    .with_system(presentation_systems::rebuild_visual_tiles.system())
+                    .with_system(presentation_systems::update_log_text.system())
+                    .with_system(presentation_systems::update_inventory_text.system())
+                    .with_system(presentation_systems::update_journal_text.system())
+                    .with_system(presentation_systems::update_dialogue_text.system()),
             );
     }
 }
 
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        use resources::*;
+
+        app.insert_resource(TurnLimit(self.turn_limit))
+            .add_system_to_stage(
+                PLAYER_INPUT,
+                headless_systems::random_walk_input.before(AUTO_MOVE_INPUT),
+            )
+            .add_system(headless_systems::stop_simulation);
+    }
+}
+
+/// Command line options; everything is optional.
+struct CliArgs {
+    /// Run the simulation with no window, driving the player with a bot
+    headless: bool,
+    /// How many turns a headless run gets before it quits
+    turn_limit: usize,
+    /// World generation seed; picked at random if not specified
+    seed: Option<u64>,
+    /// Where the game is saved to (F5) and loaded from (F9, or L on the main menu)
+    save_file: PathBuf,
+    /// Start from the save file instead of a fresh dungeon
+    load: bool,
+    /// Allow diagonal steps; can also be changed from the pause screen
+    diagonals: bool,
+    /// Where the key bindings are kept; the defaults are used if it doesn't exist yet
+    key_bindings_file: PathBuf,
+}
+
+impl CliArgs {
+    fn parse() -> Self {
+        let mut out = CliArgs {
+            headless: false,
+            turn_limit: 1000,
+            seed: None,
+            save_file: PathBuf::from("savegame.ron"),
+            load: false,
+            diagonals: false,
+            key_bindings_file: PathBuf::from("key_bindings.ron"),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => out.headless = true,
+                "--turns" => {
+                    out.turn_limit = args
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .expect("--turns requires a number");
+                }
+                "--seed" => {
+                    out.seed = Some(
+                        args.next()
+                            .and_then(|s| s.parse().ok())
+                            .expect("--seed requires a number"),
+                    );
+                }
+                "--load" => {
+                    out.save_file = args
+                        .next()
+                        .map(PathBuf::from)
+                        .expect("--load requires a file");
+                    out.load = true;
+                }
+                "--diagonals" => out.diagonals = true,
+                "--key-bindings" => {
+                    out.key_bindings_file = args
+                        .next()
+                        .map(PathBuf::from)
+                        .expect("--key-bindings requires a file");
+                }
+                other => panic!("Unrecognized argument {}", other),
+            }
+        }
+
+        out
+    }
+}
+
 pub fn main() {
     use map::TILE_SIZE;
 
+    use resources::{GameSettings, GameState};
+
+    let args = CliArgs::parse();
+    // with nobody to look at the menu, go straight to the game; same if asked to load a save
+    let initial_state = if args.headless || args.load {
+        GameState::InGame
+    } else {
+        GameState::MainMenu
+    };
+    let simulation = SimulationPlugin {
+        seed: args.seed,
+        save_file: args.save_file,
+        load_on_start: args.load,
+        initial_state,
+        settings: GameSettings {
+            diagonal_movement: args.diagonals,
+        },
+    };
+
+    if args.headless {
+        App::new()
+            .add_plugins(MinimalPlugins)
+            .add_plugin(simulation)
+            .add_plugin(HeadlessPlugin {
+                turn_limit: args.turn_limit,
+            })
+            .run();
+        return;
+    }
+
     App::new()
         .insert_resource(WindowDescriptor {
             title: "".to_string(),
@@ -141,7 +437,10 @@ pub fn main() {
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
-        .add_plugin(MapPlugin)
+        .add_plugin(simulation)
+        .add_plugin(PresentationPlugin {
+            key_bindings_file: args.key_bindings_file,
+        })
         .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .run();
 }
@@ -1,39 +1,47 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
-use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::components::WorldPos;
 
+mod grid;
+
+use grid::{BitGrid, Grid};
+
 pub const TILE_SIZE: f32 = 32.0;
 
 pub const MAP_WIDTH_TILES: i32 = 41;
 pub const MAP_HEIGHT_TILES: i32 = 41;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub enum TileType {
     Wall,
     Floor,
+    /// Leads to the next level down
+    DownStairs,
+    /// Leads back to the level above
+    UpStairs,
 }
 
 impl TileType {
     pub fn blocks_visibility(&self) -> bool {
         match *self {
             TileType::Wall => true,
-            TileType::Floor => false,
+            TileType::Floor | TileType::DownStairs | TileType::UpStairs => false,
         }
     }
 
     pub fn blocks_movement(&self) -> bool {
         match *self {
             TileType::Wall => true,
-            TileType::Floor => false,
+            TileType::Floor | TileType::DownStairs | TileType::UpStairs => false,
         }
     }
 }
 
 /// Bounding box (also used as a rectangle). All coordinates are in world (tile) coordinates,
 /// and all coordinates are inclusive.
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub struct BoundingBox {
     pub x_min: i32,
     pub x_max: i32,
@@ -119,24 +127,28 @@ impl Default for BoundingBox {
 }
 
 pub struct Map {
-    // TODO perf: HashMap is probably not sustainable but it solves a lot of indexing problems
     default_tile: TileType,
-    tiles: HashMap<WorldPos, TileType>,
+    /// None means the tile was never set (so it reads as the default, but isn't drawn)
+    tiles: Grid<Option<TileType>>,
     rooms: Vec<BoundingBox>,
     bounds: BoundingBox,
-    visible: HashSet<WorldPos>,
-    seen: HashSet<WorldPos>,
+    visible: BitGrid,
+    seen: BitGrid,
+    /// Whether things can step diagonally. This is a game setting rather than part of the level,
+    /// so it isn't saved with it; see `GameSettings`.
+    diagonals: bool,
 }
 
 impl Map {
     pub fn new() -> Self {
         Map {
             default_tile: TileType::Wall,
-            tiles: HashMap::new(),
+            tiles: Grid::new(None),
             rooms: Vec::new(),
             bounds: BoundingBox::default(),
-            seen: HashSet::new(),
-            visible: HashSet::new(),
+            seen: BitGrid::new(),
+            visible: BitGrid::new(),
+            diagonals: false,
         }
     }
 
@@ -144,8 +156,27 @@ impl Map {
         self.bounds
     }
 
+    /// Carve out a room and remember it
+    pub fn add_room(&mut self, room: BoundingBox) {
+        for x in room.x_min..room.x_max + 1 {
+            for y in room.y_min..room.y_max + 1 {
+                self.set_tile(WorldPos { x, y }, TileType::Floor);
+            }
+        }
+
+        self.rooms.push(room);
+    }
+
+    /// First place (in row-major order) with the given tile type, if any
+    pub fn find_tile(&self, tile: TileType) -> Option<WorldPos> {
+        self.tiles
+            .iter()
+            .find(|(_, tt)| *tt == Some(tile))
+            .map(|(wp, _)| wp)
+    }
+
     pub fn get_tile(&self, wp: WorldPos) -> TileType {
-        self.tiles.get(&wp).copied().unwrap_or(self.default_tile)
+        self.tiles.get(wp).unwrap_or(self.default_tile)
     }
 
     pub fn passable(&self, wp: WorldPos) -> bool {
@@ -153,8 +184,10 @@ impl Map {
     }
 
     pub fn set_if_empty(&mut self, wp: WorldPos, tile: TileType) {
-        // This feels like it should be a normal hashmap thing but who knows
-        self.tiles.entry(wp).or_insert(tile);
+        let existing = self.tiles.get_mut(wp);
+        if existing.is_none() {
+            *existing = Some(tile);
+        }
         self.bounds.include_pt(wp);
     }
 
@@ -164,36 +197,75 @@ impl Map {
                 self.set_if_empty(WorldPos { x, y }, self.default_tile);
             }
         }
-        self.tiles.insert(wp, tile);
+        self.tiles.set(wp, Some(tile));
         self.bounds.include_pt(wp);
     }
 
     pub fn tiles(&self) -> Box<dyn Iterator<Item = TileData> + '_> {
-        let out = self.tiles.iter().map(|(wp, tt)| TileData {
-            world_pos: *wp,
-            tile_type: *tt,
-            seen: self.seen.contains(wp),
-            visible: self.visible.contains(wp),
+        let out = self.tiles.iter().filter_map(|(wp, tt)| {
+            tt.map(|tile_type| TileData {
+                world_pos: wp,
+                tile_type,
+                seen: self.seen.get(wp),
+                visible: self.visible.get(wp),
+            })
         });
         Box::new(out)
     }
 
-    /// Returns adjacent tiles which are passable
+    pub fn diagonals(&self) -> bool {
+        self.diagonals
+    }
+
+    pub fn set_diagonals(&mut self, diagonals: bool) {
+        self.diagonals = diagonals;
+    }
+
+    /// Returns adjacent tiles which are passable. With diagonals on that includes the corners,
+    /// as long as there's no wall to squeeze past on the way.
     pub fn adjacent(&self, wp: WorldPos) -> Box<dyn Iterator<Item = WorldPos> + '_> {
         let WorldPos { x, y } = wp;
-        let out = [(x, y - 1), (x - 1, y), (x, y + 1), (x + 1, y)]
+        let orthogonal = [(x, y - 1), (x - 1, y), (x, y + 1), (x + 1, y)]
+            .into_iter()
+            .map(|(x, y)| WorldPos { x, y });
+        let diagonal = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
             .into_iter()
-            .map(|(x, y)| WorldPos { x, y })
-            .filter(|wp| {
-                let tt = self.tiles.get(wp).copied().unwrap_or(self.default_tile);
-                !tt.blocks_movement()
+            .filter(move |_| self.diagonals)
+            .filter(move |(dx, dy)| {
+                self.passable(WorldPos { x: x + dx, y }) && self.passable(WorldPos { x, y: y + dy })
+            })
+            .map(move |(dx, dy)| WorldPos {
+                x: x + dx,
+                y: y + dy,
             });
+        let out = orthogonal.chain(diagonal).filter(|wp| self.passable(*wp));
         Box::new(out)
     }
 
+    /// Whether a single step gets from one tile to the other. This is also how far melee
+    /// reaches, so nobody can hit around a corner they couldn't step around.
+    pub fn is_adjacent(&self, from: WorldPos, to: WorldPos) -> bool {
+        self.adjacent(from).any(|wp| wp == to)
+    }
+
+    /// How many steps apart two tiles are on open ground, which depends on whether diagonal steps
+    /// are allowed
+    pub fn step_distance(&self, a: WorldPos, b: WorldPos) -> i32 {
+        if self.diagonals {
+            a.chebyshev_dist(b)
+        } else {
+            a.dist(b)
+        }
+    }
+
+    /// Whether the player has ever laid eyes on the tile
+    pub fn is_seen(&self, wp: WorldPos) -> bool {
+        self.seen.get(wp)
+    }
+
     pub fn mark_visible(&mut self, wp: WorldPos) {
-        self.visible.insert(wp);
-        self.seen.insert(wp);
+        self.visible.set(wp, true);
+        self.seen.set(wp, true);
     }
 
     pub fn set_visible_exact(&mut self, visible: &HashSet<WorldPos>) {
@@ -205,99 +277,66 @@ impl Map {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
-pub struct TileData {
-    pub world_pos: WorldPos,
-    pub tile_type: TileType,
-    pub seen: bool,
-    pub visible: bool,
+/// Flattened copy of a Map, for saving. Everything is in row-major order so the same map always
+/// comes out the same way, which keeps save files diffable.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapSnapshot {
+    default_tile: TileType,
+    bounds: BoundingBox,
+    rooms: Vec<BoundingBox>,
+    tiles: Vec<(WorldPos, TileType)>,
+    visible: Vec<WorldPos>,
+    seen: Vec<WorldPos>,
 }
 
-impl Default for Map {
-    fn default() -> Self {
-        Map::new()
+impl Map {
+    pub fn snapshot(&self) -> MapSnapshot {
+        MapSnapshot {
+            default_tile: self.default_tile,
+            bounds: self.bounds,
+            rooms: self.rooms.clone(),
+            tiles: self
+                .tiles
+                .iter()
+                .filter_map(|(wp, tt)| tt.map(|tt| (wp, tt)))
+                .collect(),
+            visible: self.visible.iter().collect(),
+            seen: self.seen.iter().collect(),
+        }
     }
-}
-
-pub fn make_new_map() -> (Map, Vec<BoundingBox>) {
-    let mut map = Map::new();
-
-    const MIN_SIZE: i32 = 3;
-    const MAX_SIZE: i32 = 5;
-    const MAX_ROOMS: i32 = 30;
-
-    let mut rng = rand::thread_rng();
-    let mut rooms: Vec<BoundingBox> = Vec::new();
 
-    for _ in 0..MAX_ROOMS {
-        let w = rng.gen_range(MIN_SIZE..=MAX_SIZE);
-        let h = rng.gen_range(MIN_SIZE..=MAX_SIZE);
-        let x = rng.gen_range(1..MAP_WIDTH_TILES - w - 1);
-        let y = rng.gen_range(1..MAP_HEIGHT_TILES - h - 1);
-
-        let room_box = BoundingBox {
-            x_min: x,
-            x_max: x + w - 1,
-            y_min: y,
-            y_max: y + h - 1,
+    pub fn from_snapshot(snapshot: MapSnapshot) -> Map {
+        let mut map = Map {
+            default_tile: snapshot.default_tile,
+            rooms: snapshot.rooms,
+            bounds: snapshot.bounds,
+            ..Map::new()
         };
 
-        // TODO: needs a border; this allows two rooms' walls to be inside the other one's room
-        let mut ok = true;
-        for other in &rooms {
-            if other.intersects(&room_box, 1) {
-                ok = false;
-                break;
-            }
+        for (wp, tt) in snapshot.tiles {
+            map.tiles.set(wp, Some(tt));
         }
-
-        if ok {
-            rooms.push(room_box);
-            apply_room_to_map(&mut map, room_box);
+        for wp in snapshot.visible {
+            map.visible.set(wp, true);
         }
-    }
-
-    for i in 0..rooms.len() - 1 {
-        let (old_x, old_y) = rooms[i].center();
-        let (new_x, new_y) = rooms[i + 1].center();
-        if rng.gen_range(0..2) == 0 {
-            apply_horizontal_tunnel(&mut map, old_x, new_x, old_y);
-            apply_vertical_tunnel(&mut map, old_y, new_y, new_x);
-        } else {
-            apply_vertical_tunnel(&mut map, old_y, new_y, old_x);
-            apply_horizontal_tunnel(&mut map, old_x, new_x, new_y);
+        for wp in snapshot.seen {
+            map.seen.set(wp, true);
         }
-    }
-
-    (map, rooms)
-}
 
-fn apply_room_to_map(map: &mut Map, room: BoundingBox) {
-    for x in room.x_min..room.x_max + 1 {
-        for y in room.y_min..room.y_max + 1 {
-            map.set_tile(WorldPos { x, y }, TileType::Floor);
-        }
+        map
     }
-
-    map.rooms.push(room);
 }
 
-fn apply_horizontal_tunnel(map: &mut Map, mut old_x: i32, mut new_x: i32, y: i32) {
-    if old_x > new_x {
-        std::mem::swap(&mut old_x, &mut new_x);
-    }
-
-    for x in old_x..new_x + 1 {
-        map.set_tile(WorldPos { x, y }, TileType::Floor);
-    }
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TileData {
+    pub world_pos: WorldPos,
+    pub tile_type: TileType,
+    pub seen: bool,
+    pub visible: bool,
 }
 
-fn apply_vertical_tunnel(map: &mut Map, mut old_y: i32, mut new_y: i32, x: i32) {
-    if old_y > new_y {
-        std::mem::swap(&mut old_y, &mut new_y);
-    }
-
-    for y in old_y..new_y + 1 {
-        map.set_tile(WorldPos { x, y }, TileType::Floor);
+impl Default for Map {
+    fn default() -> Self {
+        Map::new()
     }
 }
@@ -0,0 +1,194 @@
+//! Dense storage for per-tile data. Coordinates can be anything; the storage grows to fit whatever
+//! gets written, and anything that was never written reads back as the fill value.
+
+use super::BoundingBox;
+use crate::components::WorldPos;
+
+fn contains(area: &BoundingBox, wp: WorldPos) -> bool {
+    !area.empty()
+        && area.x_min <= wp.x
+        && wp.x <= area.x_max
+        && area.y_min <= wp.y
+        && wp.y <= area.y_max
+}
+
+/// Row-major index of the given point in the area, if it's in there at all
+fn index(area: &BoundingBox, wp: WorldPos) -> Option<usize> {
+    if contains(area, wp) {
+        let dx = wp.x - area.x_min;
+        let dy = wp.y - area.y_min;
+        Some((dy * area.width() + dx) as usize)
+    } else {
+        None
+    }
+}
+
+/// Inverse of `index`
+fn position(area: &BoundingBox, idx: usize) -> WorldPos {
+    let idx = idx as i32;
+    WorldPos {
+        x: area.x_min + idx % area.width(),
+        y: area.y_min + idx / area.width(),
+    }
+}
+
+/// A bigger area which includes the point, with some room to spare in the direction of growth,
+/// so writing a row of tiles one at a time doesn't reallocate every time.
+fn grown_to_include(area: &BoundingBox, wp: WorldPos) -> BoundingBox {
+    const MIN_SLACK: i32 = 8;
+
+    let x_slack = (area.width() / 2).max(MIN_SLACK);
+    let y_slack = (area.height() / 2).max(MIN_SLACK);
+
+    if area.empty() {
+        return BoundingBox {
+            x_min: wp.x - x_slack,
+            x_max: wp.x + x_slack,
+            y_min: wp.y - y_slack,
+            y_max: wp.y + y_slack,
+        };
+    }
+
+    let mut out = *area;
+    if wp.x < out.x_min {
+        out.x_min = wp.x - x_slack;
+    }
+    if wp.x > out.x_max {
+        out.x_max = wp.x + x_slack;
+    }
+    if wp.y < out.y_min {
+        out.y_min = wp.y - y_slack;
+    }
+    if wp.y > out.y_max {
+        out.y_max = wp.y + y_slack;
+    }
+    out
+}
+
+fn cell_count(area: &BoundingBox) -> usize {
+    (area.width() * area.height()) as usize
+}
+
+/// Flat array of values covering a (growable) rectangle of the world
+#[derive(Clone, Debug)]
+pub struct Grid<T> {
+    area: BoundingBox,
+    cells: Vec<T>,
+    fill: T,
+}
+
+impl<T: Copy> Grid<T> {
+    pub fn new(fill: T) -> Self {
+        Grid {
+            area: BoundingBox::make_empty(),
+            cells: Vec::new(),
+            fill,
+        }
+    }
+
+    pub fn get(&self, wp: WorldPos) -> T {
+        match index(&self.area, wp) {
+            Some(idx) => self.cells[idx],
+            None => self.fill,
+        }
+    }
+
+    pub fn get_mut(&mut self, wp: WorldPos) -> &mut T {
+        self.grow_to_include(wp);
+        let idx = index(&self.area, wp).expect("Grid was just grown to include this");
+        &mut self.cells[idx]
+    }
+
+    pub fn set(&mut self, wp: WorldPos, value: T) {
+        *self.get_mut(wp) = value;
+    }
+
+    /// Every stored cell, in row-major order (so sorted by y, then x)
+    pub fn iter(&self) -> impl Iterator<Item = (WorldPos, T)> + '_ {
+        let area = self.area;
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(idx, value)| (position(&area, idx), *value))
+    }
+
+    fn grow_to_include(&mut self, wp: WorldPos) {
+        if contains(&self.area, wp) {
+            return;
+        }
+
+        let new_area = grown_to_include(&self.area, wp);
+        let mut new_cells = vec![self.fill; cell_count(&new_area)];
+        for (old_wp, value) in self.iter() {
+            let idx = index(&new_area, old_wp).expect("New area contains the old one");
+            new_cells[idx] = value;
+        }
+
+        self.area = new_area;
+        self.cells = new_cells;
+    }
+}
+
+/// Like a `Grid<bool>` but packed into bits; everything not set reads as false
+#[derive(Clone, Debug, Default)]
+pub struct BitGrid {
+    area: BoundingBox,
+    words: Vec<u64>,
+}
+
+impl BitGrid {
+    pub fn new() -> Self {
+        BitGrid::default()
+    }
+
+    pub fn get(&self, wp: WorldPos) -> bool {
+        match index(&self.area, wp) {
+            Some(idx) => self.words[idx / 64] & (1 << (idx % 64)) != 0,
+            None => false,
+        }
+    }
+
+    pub fn set(&mut self, wp: WorldPos, value: bool) {
+        // everything outside the area is already false, so no reason to grow for that
+        if !value && !contains(&self.area, wp) {
+            return;
+        }
+
+        self.grow_to_include(wp);
+        let idx = index(&self.area, wp).expect("Grid was just grown to include this");
+        if value {
+            self.words[idx / 64] |= 1 << (idx % 64);
+        } else {
+            self.words[idx / 64] &= !(1 << (idx % 64));
+        }
+    }
+
+    /// Set everything to false (keeping the storage around for reuse)
+    pub fn clear(&mut self) {
+        self.words.iter_mut().for_each(|w| *w = 0);
+    }
+
+    /// Every position which is set, in row-major order (so sorted by y, then x)
+    pub fn iter(&self) -> impl Iterator<Item = WorldPos> + '_ {
+        let area = self.area;
+        (0..cell_count(&area))
+            .filter(move |idx| self.words[idx / 64] & (1 << (idx % 64)) != 0)
+            .map(move |idx| position(&area, idx))
+    }
+
+    fn grow_to_include(&mut self, wp: WorldPos) {
+        if contains(&self.area, wp) {
+            return;
+        }
+
+        let new_area = grown_to_include(&self.area, wp);
+        let mut new_words = vec![0; (cell_count(&new_area) + 63) / 64];
+        for old_wp in self.iter() {
+            let idx = index(&new_area, old_wp).expect("New area contains the old one");
+            new_words[idx / 64] |= 1 << (idx % 64);
+        }
+
+        self.area = new_area;
+        self.words = new_words;
+    }
+}
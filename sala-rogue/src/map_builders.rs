@@ -0,0 +1,210 @@
+//! Different ways of laying out a level. Every builder makes a `Map`, decides where the player
+//! starts, and carves the rest of the level up into regions that things can be spawned into.
+
+use std::collections::BTreeMap;
+
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::components::WorldPos;
+use crate::map::*;
+
+mod bsp;
+mod caves;
+mod drunkard;
+mod maze;
+mod simple_rooms;
+mod vaults;
+
+pub use bsp::BspBuilder;
+pub use caves::CavesBuilder;
+pub use drunkard::DrunkardsWalkBuilder;
+pub use maze::MazeBuilder;
+pub use simple_rooms::SimpleRoomsBuilder;
+pub use vaults::VaultsBuilder;
+
+/// Everything a builder comes up with
+pub struct BuiltLevel {
+    pub map: Map,
+    pub player_start: WorldPos,
+    /// Places to put monsters; one monster per region. None of these include the player start.
+    pub spawn_regions: Vec<Vec<WorldPos>>,
+    /// Parts of the level that get remarked on when the player first sees them
+    pub tagged_rooms: Vec<TaggedRoom>,
+}
+
+/// A named part of a level, like a vault
+pub struct TaggedRoom {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub tiles: Vec<WorldPos>,
+}
+
+pub trait MapBuilder {
+    /// Lay out a level. All randomness has to come out of the rng, so levels are reproducible.
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel;
+}
+
+/// The kinds of builders that can be named in the raws
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+pub enum MapStyle {
+    SimpleRooms,
+    Bsp,
+    Caves,
+    DrunkardsWalk,
+    Maze,
+}
+
+pub fn make_builder(style: MapStyle, with_vaults: bool) -> Box<dyn MapBuilder> {
+    let base: Box<dyn MapBuilder> = match style {
+        MapStyle::SimpleRooms => Box::new(SimpleRoomsBuilder),
+        MapStyle::Bsp => Box::new(BspBuilder),
+        MapStyle::Caves => Box::new(CavesBuilder),
+        MapStyle::DrunkardsWalk => Box::new(DrunkardsWalkBuilder),
+        MapStyle::Maze => Box::new(MazeBuilder),
+    };
+
+    if with_vaults {
+        Box::new(VaultsBuilder { base })
+    } else {
+        base
+    }
+}
+
+fn apply_horizontal_tunnel(map: &mut Map, mut old_x: i32, mut new_x: i32, y: i32) {
+    if old_x > new_x {
+        std::mem::swap(&mut old_x, &mut new_x);
+    }
+
+    for x in old_x..new_x + 1 {
+        map.set_tile(WorldPos { x, y }, TileType::Floor);
+    }
+}
+
+fn apply_vertical_tunnel(map: &mut Map, mut old_y: i32, mut new_y: i32, x: i32) {
+    if old_y > new_y {
+        std::mem::swap(&mut old_y, &mut new_y);
+    }
+
+    for y in old_y..new_y + 1 {
+        map.set_tile(WorldPos { x, y }, TileType::Floor);
+    }
+}
+
+/// L-shaped tunnel between the centers of two rooms
+fn connect_rooms(map: &mut Map, a: BoundingBox, b: BoundingBox, horizontal_first: bool) {
+    let (old_x, old_y) = a.center();
+    let (new_x, new_y) = b.center();
+    if horizontal_first {
+        apply_horizontal_tunnel(map, old_x, new_x, old_y);
+        apply_vertical_tunnel(map, old_y, new_y, new_x);
+    } else {
+        apply_vertical_tunnel(map, old_y, new_y, old_x);
+        apply_horizontal_tunnel(map, old_x, new_x, new_y);
+    }
+}
+
+fn room_tiles(room: &BoundingBox) -> Vec<WorldPos> {
+    let mut out = Vec::new();
+    for y in room.y_min..room.y_max + 1 {
+        for x in room.x_min..room.x_max + 1 {
+            out.push(WorldPos { x, y });
+        }
+    }
+    out
+}
+
+/// For builders without rooms: chop the passable tiles up into square chunks and use those as
+/// spawn regions. Chunks too close to the player start are skipped, so nothing spawns on top of
+/// them.
+fn regions_by_area(map: &Map, player_start: WorldPos, chunk_size: i32) -> Vec<Vec<WorldPos>> {
+    let mut chunks: BTreeMap<(i32, i32), Vec<WorldPos>> = BTreeMap::new();
+
+    for tile in map.tiles() {
+        let wp = tile.world_pos;
+        if !map.passable(wp) || wp.dist(player_start) <= chunk_size {
+            continue;
+        }
+
+        chunks
+            .entry((wp.y.div_euclid(chunk_size), wp.x.div_euclid(chunk_size)))
+            .or_default()
+            .push(wp);
+    }
+
+    // tiny chunks are mostly stray corners of bigger areas, and crowd things together
+    chunks
+        .into_iter()
+        .map(|(_, tiles)| tiles)
+        .filter(|tiles| tiles.len() as i32 >= chunk_size)
+        .collect()
+}
+
+/// Working space for builders which think in terms of "is this floor", over the whole map.
+/// The outer edge is always wall.
+struct FloorGrid {
+    floor: Vec<bool>,
+}
+
+impl FloorGrid {
+    fn new() -> Self {
+        FloorGrid {
+            floor: vec![false; (MAP_WIDTH_TILES * MAP_HEIGHT_TILES) as usize],
+        }
+    }
+
+    fn in_interior(wp: WorldPos) -> bool {
+        wp.x > 0 && wp.x < MAP_WIDTH_TILES - 1 && wp.y > 0 && wp.y < MAP_HEIGHT_TILES - 1
+    }
+
+    fn get(&self, wp: WorldPos) -> bool {
+        Self::in_interior(wp) && self.floor[(wp.y * MAP_WIDTH_TILES + wp.x) as usize]
+    }
+
+    fn set(&mut self, wp: WorldPos, floor: bool) {
+        if Self::in_interior(wp) {
+            self.floor[(wp.y * MAP_WIDTH_TILES + wp.x) as usize] = floor;
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.floor.iter().filter(|f| **f).count()
+    }
+
+    /// Every interior tile, in row-major order
+    fn interior() -> impl Iterator<Item = WorldPos> {
+        (1..MAP_HEIGHT_TILES - 1)
+            .flat_map(|y| (1..MAP_WIDTH_TILES - 1).map(move |x| WorldPos { x, y }))
+    }
+
+    /// Turn any floor which can't be walked to from the start back into wall
+    fn remove_unreachable(&mut self, start: WorldPos) {
+        let mut reachable = vec![false; self.floor.len()];
+        let mut to_process = vec![start];
+
+        while let Some(wp) = to_process.pop() {
+            let idx = (wp.y * MAP_WIDTH_TILES + wp.x) as usize;
+            if !self.get(wp) || reachable[idx] {
+                continue;
+            }
+            reachable[idx] = true;
+
+            let WorldPos { x, y } = wp;
+            for (x, y) in [(x, y - 1), (x - 1, y), (x, y + 1), (x + 1, y)] {
+                to_process.push(WorldPos { x, y });
+            }
+        }
+
+        self.floor = reachable;
+    }
+
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        for wp in Self::interior() {
+            if self.get(wp) {
+                map.set_tile(wp, TileType::Floor);
+            }
+        }
+        map
+    }
+}
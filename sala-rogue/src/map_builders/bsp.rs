@@ -0,0 +1,101 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// Leaves smaller than this (in either direction) don't get split any further
+const MIN_LEAF_SIZE: i32 = 8;
+const MAX_ROOM_SIZE: i32 = 8;
+
+/// Binary space partition: keep cutting the map in half (at a random spot), put a room in each
+/// piece, then join the rooms up in the order they were cut, so neighbors connect to neighbors.
+pub struct BspBuilder;
+
+impl MapBuilder for BspBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut map = Map::new();
+
+        let whole = BoundingBox {
+            x_min: 1,
+            x_max: MAP_WIDTH_TILES - 2,
+            y_min: 1,
+            y_max: MAP_HEIGHT_TILES - 2,
+        };
+
+        let mut leaves = Vec::new();
+        split(whole, rng, &mut leaves);
+
+        let rooms: Vec<BoundingBox> = leaves.iter().map(|leaf| room_in(leaf, rng)).collect();
+
+        for room in rooms.iter() {
+            map.add_room(*room);
+        }
+
+        for pair in rooms.windows(2) {
+            let horizontal_first = rng.gen_bool(0.5);
+            connect_rooms(&mut map, pair[0], pair[1], horizontal_first);
+        }
+
+        let (x, y) = rooms[0].center();
+
+        BuiltLevel {
+            map,
+            player_start: WorldPos { x, y },
+            spawn_regions: rooms.iter().skip(1).map(room_tiles).collect(),
+            tagged_rooms: Vec::new(),
+        }
+    }
+}
+
+fn split(area: BoundingBox, rng: &mut StdRng, leaves: &mut Vec<BoundingBox>) {
+    let can_split_x = area.width() >= 2 * MIN_LEAF_SIZE;
+    let can_split_y = area.height() >= 2 * MIN_LEAF_SIZE;
+
+    let split_x = match (can_split_x, can_split_y) {
+        (false, false) => {
+            leaves.push(area);
+            return;
+        }
+        (true, false) => true,
+        (false, true) => false,
+        (true, true) => rng.gen_bool(0.5),
+    };
+
+    let (a, b) = if split_x {
+        let at = rng.gen_range(area.x_min + MIN_LEAF_SIZE..=area.x_max + 1 - MIN_LEAF_SIZE);
+        (
+            BoundingBox {
+                x_max: at - 1,
+                ..area
+            },
+            BoundingBox { x_min: at, ..area },
+        )
+    } else {
+        let at = rng.gen_range(area.y_min + MIN_LEAF_SIZE..=area.y_max + 1 - MIN_LEAF_SIZE);
+        (
+            BoundingBox {
+                y_max: at - 1,
+                ..area
+            },
+            BoundingBox { y_min: at, ..area },
+        )
+    };
+
+    split(a, rng, leaves);
+    split(b, rng, leaves);
+}
+
+/// A random room inside the leaf, leaving at least one tile of wall on every side
+fn room_in(leaf: &BoundingBox, rng: &mut StdRng) -> BoundingBox {
+    let w = rng.gen_range(3..=(leaf.width() - 2).min(MAX_ROOM_SIZE));
+    let h = rng.gen_range(3..=(leaf.height() - 2).min(MAX_ROOM_SIZE));
+    let x = rng.gen_range(leaf.x_min + 1..=leaf.x_max - w);
+    let y = rng.gen_range(leaf.y_min + 1..=leaf.y_max - h);
+
+    BoundingBox {
+        x_min: x,
+        x_max: x + w - 1,
+        y_min: y,
+        y_max: y + h - 1,
+    }
+}
@@ -0,0 +1,65 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// Odds that a tile starts out as floor, before smoothing
+const INITIAL_FLOOR_CHANCE: f64 = 0.5;
+const SMOOTHING_PASSES: usize = 5;
+
+/// Cellular automata caves: start from noise, then repeatedly make each tile look more like its
+/// neighbors. Anything that ends up cut off from the start is filled back in.
+pub struct CavesBuilder;
+
+impl MapBuilder for CavesBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut grid = FloorGrid::new();
+        for wp in FloorGrid::interior() {
+            grid.set(wp, rng.gen_bool(INITIAL_FLOOR_CHANCE));
+        }
+
+        for _ in 0..SMOOTHING_PASSES {
+            let mut next = FloorGrid::new();
+            for wp in FloorGrid::interior() {
+                // counting the tile itself, so there are no ties
+                let mut walls = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let neighbor = WorldPos {
+                            x: wp.x + dx,
+                            y: wp.y + dy,
+                        };
+                        if !grid.get(neighbor) {
+                            walls += 1;
+                        }
+                    }
+                }
+                next.set(wp, walls < 5);
+            }
+            grid = next;
+        }
+
+        // start as close to the middle as we can
+        let middle = WorldPos {
+            x: MAP_WIDTH_TILES / 2,
+            y: MAP_HEIGHT_TILES / 2,
+        };
+        let player_start = FloorGrid::interior()
+            .filter(|wp| grid.get(*wp))
+            .min_by_key(|wp| wp.dist(middle))
+            .unwrap_or(middle);
+        grid.set(player_start, true);
+
+        grid.remove_unreachable(player_start);
+
+        let map = grid.to_map();
+        let spawn_regions = regions_by_area(&map, player_start, 7);
+
+        BuiltLevel {
+            map,
+            player_start,
+            spawn_regions,
+            tagged_rooms: Vec::new(),
+        }
+    }
+}
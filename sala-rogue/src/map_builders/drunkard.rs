@@ -0,0 +1,68 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// Stop digging once this much of the map is floor
+const DESIRED_FLOOR_FRACTION: f32 = 0.4;
+/// How far each digger wanders before the next one starts
+const DIGGER_LIFETIME: usize = 200;
+
+/// Drunkard's walk: diggers stagger around from the start, turning everything they step on into
+/// floor. Always connected, and makes winding, organic tunnels.
+pub struct DrunkardsWalkBuilder;
+
+impl MapBuilder for DrunkardsWalkBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut grid = FloorGrid::new();
+
+        let player_start = WorldPos {
+            x: MAP_WIDTH_TILES / 2,
+            y: MAP_HEIGHT_TILES / 2,
+        };
+        grid.set(player_start, true);
+
+        let interior_size = ((MAP_WIDTH_TILES - 2) * (MAP_HEIGHT_TILES - 2)) as f32;
+        let desired_floor = (interior_size * DESIRED_FLOOR_FRACTION) as usize;
+
+        while grid.count() < desired_floor {
+            let mut digger = player_start;
+            for _ in 0..DIGGER_LIFETIME {
+                let next = match rng.gen_range(0..4) {
+                    0 => WorldPos {
+                        y: digger.y + 1,
+                        ..digger
+                    },
+                    1 => WorldPos {
+                        y: digger.y - 1,
+                        ..digger
+                    },
+                    2 => WorldPos {
+                        x: digger.x + 1,
+                        ..digger
+                    },
+                    _ => WorldPos {
+                        x: digger.x - 1,
+                        ..digger
+                    },
+                };
+
+                // bounce off the edge of the map
+                if FloorGrid::in_interior(next) {
+                    digger = next;
+                    grid.set(digger, true);
+                }
+            }
+        }
+
+        let map = grid.to_map();
+        let spawn_regions = regions_by_area(&map, player_start, 7);
+
+        BuiltLevel {
+            map,
+            player_start,
+            spawn_regions,
+            tagged_rooms: Vec::new(),
+        }
+    }
+}
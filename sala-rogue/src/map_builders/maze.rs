@@ -0,0 +1,54 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// A perfect maze (exactly one path between any two points) from a randomized depth-first
+/// search. Maze "cells" are the tiles with odd coordinates; the even ones are walls or the
+/// passages knocked through them.
+pub struct MazeBuilder;
+
+impl MapBuilder for MazeBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut grid = FloorGrid::new();
+
+        let player_start = WorldPos { x: 1, y: 1 };
+        grid.set(player_start, true);
+
+        let mut stack = vec![player_start];
+        while let Some(&current) = stack.last() {
+            let unvisited: Vec<WorldPos> = [(0, 2), (0, -2), (2, 0), (-2, 0)]
+                .into_iter()
+                .map(|(dx, dy)| WorldPos {
+                    x: current.x + dx,
+                    y: current.y + dy,
+                })
+                .filter(|wp| FloorGrid::in_interior(*wp) && !grid.get(*wp))
+                .collect();
+
+            if unvisited.is_empty() {
+                stack.pop();
+                continue;
+            }
+
+            let next = unvisited[rng.gen_range(0..unvisited.len())];
+            let between = WorldPos {
+                x: (current.x + next.x) / 2,
+                y: (current.y + next.y) / 2,
+            };
+            grid.set(between, true);
+            grid.set(next, true);
+            stack.push(next);
+        }
+
+        let map = grid.to_map();
+        let spawn_regions = regions_by_area(&map, player_start, 6);
+
+        BuiltLevel {
+            map,
+            player_start,
+            spawn_regions,
+            tagged_rooms: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// Random non-overlapping rooms, each joined to the next by an L-shaped tunnel
+pub struct SimpleRoomsBuilder;
+
+impl MapBuilder for SimpleRoomsBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut map = Map::new();
+
+        const MIN_SIZE: i32 = 3;
+        const MAX_SIZE: i32 = 5;
+        const MAX_ROOMS: i32 = 30;
+
+        let mut rooms: Vec<BoundingBox> = Vec::new();
+
+        for _ in 0..MAX_ROOMS {
+            let w = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+            let h = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+            let x = rng.gen_range(1..MAP_WIDTH_TILES - w - 1);
+            let y = rng.gen_range(1..MAP_HEIGHT_TILES - h - 1);
+
+            let room_box = BoundingBox {
+                x_min: x,
+                x_max: x + w - 1,
+                y_min: y,
+                y_max: y + h - 1,
+            };
+
+            // TODO: needs a border; this allows two rooms' walls to be inside the other one's room
+            let mut ok = true;
+            for other in &rooms {
+                if other.intersects(&room_box, 1) {
+                    ok = false;
+                    break;
+                }
+            }
+
+            if ok {
+                rooms.push(room_box);
+                map.add_room(room_box);
+            }
+        }
+
+        for i in 0..rooms.len() - 1 {
+            let horizontal_first = rng.gen_range(0..2) == 0;
+            connect_rooms(&mut map, rooms[i], rooms[i + 1], horizontal_first);
+        }
+
+        let (x, y) = rooms[0].center();
+
+        BuiltLevel {
+            map,
+            player_start: WorldPos { x, y },
+            spawn_regions: rooms.iter().skip(1).map(room_tiles).collect(),
+            tagged_rooms: Vec::new(),
+        }
+    }
+}
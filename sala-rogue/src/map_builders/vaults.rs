@@ -0,0 +1,125 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::*;
+
+/// A hand-made set piece, and what the player is told when they first see it
+struct Vault {
+    name: &'static str,
+    description: &'static str,
+    /// '#' is wall, '.' is floor, and 'M' is floor with a monster on it. The edges should all be
+    /// floor, so stamping a vault never cuts the level in two.
+    layout: &'static [&'static str],
+}
+
+#[rustfmt::skip]
+const VAULTS: &[Vault] = &[
+    Vault {
+        name: "Pillared hall",
+        description: "Four pillars stand in a ring, and something waits between them.",
+        layout: &[
+            ".......",
+            ".#...#.",
+            "...M...",
+            ".#...#.",
+            ".......",
+        ],
+    },
+    Vault {
+        name: "Guard posts",
+        description: "Two walled alcoves face each other, each with a sentry inside.",
+        layout: &[
+            ".........",
+            ".###.###.",
+            ".#M...M#.",
+            ".###.###.",
+            ".........",
+        ],
+    },
+    Vault {
+        name: "Broken shrine",
+        description: "A crumbling shrine sits here, its keeper still on watch.",
+        layout: &[
+            ".......",
+            "..#.#..",
+            ".#.M.#.",
+            "..#.#..",
+            ".......",
+        ],
+    },
+];
+
+/// How many spots to try before giving up on fitting a vault in
+const PLACEMENT_ATTEMPTS: usize = 50;
+
+/// Builds a level with some other builder, then stamps a prefab vault somewhere open enough to
+/// hold it.
+pub struct VaultsBuilder {
+    pub base: Box<dyn MapBuilder>,
+}
+
+impl MapBuilder for VaultsBuilder {
+    fn build(&self, rng: &mut StdRng) -> BuiltLevel {
+        let mut level = self.base.build(rng);
+
+        let vault = &VAULTS[rng.gen_range(0..VAULTS.len())];
+        let height = vault.layout.len() as i32;
+        let width = vault.layout[0].len() as i32;
+
+        for _ in 0..PLACEMENT_ATTEMPTS {
+            let x_min = rng.gen_range(1..MAP_WIDTH_TILES - width);
+            let y_min = rng.gen_range(1..MAP_HEIGHT_TILES - height);
+
+            // rows are written top to bottom, but y goes up
+            let footprint: Vec<(WorldPos, char)> = vault
+                .layout
+                .iter()
+                .enumerate()
+                .flat_map(|(row_idx, row)| {
+                    row.chars().enumerate().map(move |(col_idx, c)| {
+                        let wp = WorldPos {
+                            x: x_min + col_idx as i32,
+                            y: y_min + height - 1 - row_idx as i32,
+                        };
+                        (wp, c)
+                    })
+                })
+                .collect();
+
+            let fits = footprint.iter().all(|(wp, _)| {
+                level.map.get_tile(*wp) == TileType::Floor && *wp != level.player_start
+            });
+            if !fits {
+                continue;
+            }
+
+            level.tagged_rooms.push(TaggedRoom {
+                name: vault.name,
+                description: vault.description,
+                tiles: footprint.iter().map(|(wp, _)| *wp).collect(),
+            });
+
+            for (wp, c) in footprint {
+                match c {
+                    '#' => level.map.set_tile(wp, TileType::Wall),
+                    'M' => {
+                        level.map.set_tile(wp, TileType::Floor);
+                        level.spawn_regions.push(vec![wp]);
+                    }
+                    _ => level.map.set_tile(wp, TileType::Floor),
+                }
+            }
+
+            // nothing else gets to spawn inside a wall
+            let map = &level.map;
+            for region in level.spawn_regions.iter_mut() {
+                region.retain(|wp| map.passable(*wp));
+            }
+            level.spawn_regions.retain(|region| !region.is_empty());
+
+            break;
+        }
+
+        level
+    }
+}
@@ -0,0 +1,344 @@
+//! The screens around the game itself: the main menu, the pause screen and the game over screen.
+//! Each one is a full screen UI node, spawned when its state is entered and torn down when it's
+//! left. The game underneath is only ever touched through the usual resources.
+
+use bevy::app::AppExit;
+use bevy::ecs::schedule::ShouldRun;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::key_bindings::{InputAction, KeyBindings, KeyBindingsFile};
+use crate::resources::*;
+
+/// A full screen box with some centered text in it; the first line is the title
+fn spawn_screen(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    background: Color,
+    lines: &[String],
+) {
+    spawn_screen_sized(commands, asset_server, background, lines, 30.0);
+}
+
+/// Same as `spawn_screen`, with a smaller font for screens with a lot to say
+fn spawn_screen_sized(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    background: Color,
+    lines: &[String],
+    font_size: f32,
+) {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+
+    let sections = lines
+        .iter()
+        .enumerate()
+        .map(|(idx, line)| TextSection {
+            value: format!("{}\n", line),
+            style: TextStyle {
+                font: font.clone(),
+                font_size: if idx == 0 { 60.0 } else { font_size },
+                color: Color::WHITE,
+            },
+        })
+        .collect();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: background.into(),
+            ..Default::default()
+        })
+        .insert(MenuScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text {
+                    sections,
+                    alignment: TextAlignment {
+                        vertical: VerticalAlign::Center,
+                        horizontal: HorizontalAlign::Center,
+                    },
+                },
+                ..Default::default()
+            });
+        });
+}
+
+pub fn teardown_screen(mut commands: Commands, q: Query<Entity, With<MenuScreen>>) {
+    for entity in q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// For screens that redraw themselves whenever something on them changes
+#[derive(SystemParam)]
+pub struct ScreenRedraw<'w, 's> {
+    commands: Commands<'w, 's>,
+    asset_server: Res<'w, AssetServer>,
+    screen_query: Query<'w, 's, Entity, With<MenuScreen>>,
+}
+
+impl<'w, 's> ScreenRedraw<'w, 's> {
+    /// Tears down the screen that's up, and hands back what's needed to spawn its replacement
+    fn redraw(&mut self) -> (&mut Commands<'w, 's>, &AssetServer) {
+        for entity in self.screen_query.iter() {
+            self.commands.entity(entity).despawn_recursive();
+        }
+        (&mut self.commands, &*self.asset_server)
+    }
+}
+
+pub fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    spawn_screen(
+        &mut commands,
+        &*asset_server,
+        Color::rgb(0.05, 0.05, 0.08),
+        &[
+            "Salamander".to_string(),
+            "N: New game".to_string(),
+            "L: Load game".to_string(),
+            "Q: Quit".to_string(),
+        ],
+    );
+}
+
+pub fn main_menu_input(
+    mut kb_input: ResMut<Input<KeyCode>>,
+    mut pending: ResMut<PendingSaveLoad>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // the save system switches to the game, once there's a game to switch to
+    if kb_input.clear_just_pressed(KeyCode::N) {
+        pending.0 = Some(SaveLoadAction::NewGame);
+    } else if kb_input.clear_just_pressed(KeyCode::L) {
+        pending.0 = Some(SaveLoadAction::Load);
+    } else if kb_input.clear_just_pressed(KeyCode::Q) {
+        exit.send(AppExit);
+    }
+}
+
+/// Escape (or whatever it's been rebound to) pauses the game
+pub fn in_game_menu_input(
+    mut kb_input: ResMut<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut state: ResMut<State<GameState>>,
+) {
+    // cleared, so the pause screen doesn't see the same press and unpause right away
+    if bindings.clear_just_pressed(&mut *kb_input, InputAction::Pause) {
+        let _ = state.set(GameState::Paused);
+    }
+}
+
+fn spawn_pause_screen(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    settings: &GameSettings,
+    bindings: &KeyBindings,
+) {
+    let on_off = |on: bool| if on { "on" } else { "off" };
+
+    spawn_screen(
+        commands,
+        asset_server,
+        Color::rgba(0.0, 0.0, 0.0, 0.7),
+        &[
+            "Paused".to_string(),
+            format!("{}: Resume", bindings.describe(InputAction::Pause)),
+            format!(
+                "D: Diagonal movement ({})",
+                on_off(settings.diagonal_movement)
+            ),
+            "K: Key bindings".to_string(),
+            "M: Main menu".to_string(),
+            "Q: Quit".to_string(),
+        ],
+    );
+}
+
+pub fn setup_pause_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<GameSettings>,
+    bindings: Res<KeyBindings>,
+) {
+    spawn_pause_screen(&mut commands, &*asset_server, &*settings, &*bindings);
+}
+
+pub fn pause_input(
+    mut screen: ScreenRedraw,
+    mut kb_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut settings: ResMut<GameSettings>,
+    bindings: Res<KeyBindings>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if bindings.clear_just_pressed(&mut *kb_input, InputAction::Pause) {
+        let _ = state.set(GameState::InGame);
+    } else if kb_input.clear_just_pressed(KeyCode::D) {
+        settings.diagonal_movement = !settings.diagonal_movement;
+
+        // the screen shows the settings, so it needs redrawing
+        let (commands, asset_server) = screen.redraw();
+        spawn_pause_screen(commands, asset_server, &*settings, &*bindings);
+    } else if kb_input.clear_just_pressed(KeyCode::K) {
+        let _ = state.set(GameState::KeyBindings);
+    } else if kb_input.clear_just_pressed(KeyCode::M) {
+        let _ = state.set(GameState::MainMenu);
+    } else if kb_input.clear_just_pressed(KeyCode::Q) {
+        exit.send(AppExit);
+    }
+}
+
+fn spawn_key_bindings_screen(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    bindings: &KeyBindings,
+    menu: &KeyBindingsMenu,
+) {
+    let mut lines = vec![
+        "Key bindings".to_string(),
+        "Up/Down: Choose, Enter: Rebind, Space: Add a key, Backspace: Default, Esc: Back"
+            .to_string(),
+        String::new(),
+    ];
+
+    for (idx, action) in InputAction::ALL.iter().enumerate() {
+        let marker = if idx == menu.selected { "> " } else { "" };
+        lines.push(format!(
+            "{}{}: {}",
+            marker,
+            action.describe(),
+            bindings.describe(*action)
+        ));
+    }
+
+    lines.push(String::new());
+    if menu.capturing.is_some() {
+        let action = InputAction::ALL[menu.selected];
+        lines.push(format!(
+            "Press a key for {} (Esc to cancel)",
+            action.describe()
+        ));
+    } else if let Some(message) = menu.message.as_ref() {
+        lines.push(message.clone());
+    }
+
+    spawn_screen_sized(
+        commands,
+        asset_server,
+        Color::rgba(0.0, 0.0, 0.0, 0.85),
+        &lines,
+        20.0,
+    );
+}
+
+/// Starts the screen over from the top; `key_bindings_input` draws it, since it's changed
+pub fn reset_key_bindings_menu(mut menu: ResMut<KeyBindingsMenu>) {
+    *menu = KeyBindingsMenu::default();
+}
+
+/// The menu keys here are fixed, so a bad rebinding can always be undone. Bindings are written
+/// to the file on the way out. The screen is redrawn whenever anything on it changes.
+pub fn key_bindings_input(
+    mut screen: ScreenRedraw,
+    mut kb_input: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut bindings: ResMut<KeyBindings>,
+    file: Res<KeyBindingsFile>,
+    mut menu: ResMut<KeyBindingsMenu>,
+) {
+    let action = InputAction::ALL[menu.selected];
+
+    if let Some(replace) = menu.capturing {
+        let pressed = kb_input.get_just_pressed().next().copied();
+        if kb_input.clear_just_pressed(KeyCode::Escape) {
+            menu.capturing = None;
+        } else if let Some(key) = pressed {
+            kb_input.clear_just_pressed(key);
+            menu.capturing = None;
+            menu.message = bindings.bind(action, key, replace).err();
+        }
+    } else if kb_input.clear_just_pressed(KeyCode::Escape) {
+        bindings.save(&file.0);
+        let _ = state.set(GameState::Paused);
+        return;
+    } else if kb_input.clear_just_pressed(KeyCode::Up) {
+        menu.selected = (menu.selected + InputAction::ALL.len() - 1) % InputAction::ALL.len();
+    } else if kb_input.clear_just_pressed(KeyCode::Down) {
+        menu.selected = (menu.selected + 1) % InputAction::ALL.len();
+    } else if kb_input.clear_just_pressed(KeyCode::Return) {
+        menu.capturing = Some(true);
+        menu.message = None;
+    } else if kb_input.clear_just_pressed(KeyCode::Space) {
+        menu.capturing = Some(false);
+        menu.message = None;
+    } else if kb_input.clear_just_pressed(KeyCode::Back) {
+        menu.message = bindings.reset(action).err();
+    }
+
+    if menu.is_changed() || bindings.is_changed() {
+        let (commands, asset_server) = screen.redraw();
+        spawn_key_bindings_screen(commands, asset_server, &*bindings, &*menu);
+    }
+}
+
+pub fn setup_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    info: Option<Res<GameOverInfo>>,
+) {
+    let ending = info.as_ref().and_then(|info| info.ending.clone());
+    let mut lines = vec![ending
+        .clone()
+        .unwrap_or_else(|| "You have died".to_string())];
+    if let Some(info) = info {
+        if ending.is_some() {
+            lines.push(info.cause.clone());
+            lines.push(format!("It took you {} turns.", info.turn));
+        } else {
+            lines.push(format!("{}, on depth {}.", info.cause, info.depth));
+            lines.push(format!("You survived {} turns.", info.turn));
+        }
+    }
+    lines.push(String::new());
+    lines.push("Enter: Main menu".to_string());
+
+    // dying is red; anything else gets something calmer
+    let color = match ending {
+        Some(_) => Color::rgba(0.0, 0.1, 0.3, 0.7),
+        None => Color::rgba(0.3, 0.0, 0.0, 0.7),
+    };
+    spawn_screen(&mut commands, &*asset_server, color, &lines);
+}
+
+pub fn game_over_input(mut kb_input: ResMut<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
+    if kb_input.clear_just_pressed(KeyCode::Return) {
+        let _ = state.set(GameState::MainMenu);
+    }
+}
+
+/// Run criteria for the simulation itself; nothing moves unless we're actually playing
+pub fn in_game(state: Res<State<GameState>>) -> ShouldRun {
+    if *state.current() == GameState::InGame {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// Run criteria for drawing the world; it stays visible behind the pause and game over screens,
+/// but the main menu covers it entirely
+pub fn showing_game(state: Res<State<GameState>>) -> ShouldRun {
+    if *state.current() == GameState::MainMenu {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
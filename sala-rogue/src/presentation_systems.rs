@@ -0,0 +1,539 @@
+//! Everything that turns the simulation into something a human can look at (or poke at with a
+//! keyboard). None of this is needed to actually play out turns; see `running_systems` for that.
+
+use std::collections::HashSet;
+
+use bevy::diagnostic::Diagnostics;
+use bevy::prelude::*;
+
+use crate::bevy_util::make_basic_sprite_bundle;
+use crate::components::*;
+use crate::key_bindings::{InputAction, KeyBindings};
+use crate::map::{Map, TileType, TILE_SIZE};
+use crate::raws::{DialogueRegistry, QuestRegistry};
+use crate::resources::*;
+use crate::running_systems::{available_choices, cursor_is_valid, describe_item, StoryState};
+use crate::FrameTimeDiagnosticsPlugin;
+
+pub fn get_player_input(
+    kb_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut input_state: ResMut<PlayerInputState>,
+) {
+    use InputAction::*;
+
+    *input_state = PlayerInputState::default();
+
+    let pressed = |action: InputAction| bindings.just_pressed(&*kb_input, action);
+
+    // the diagonals do nothing unless diagonal movement is on
+    input_state.left_pressed = pressed(MoveLeft) || pressed(MoveUpLeft) || pressed(MoveDownLeft);
+    input_state.right_pressed =
+        pressed(MoveRight) || pressed(MoveUpRight) || pressed(MoveDownRight);
+    input_state.up_pressed = pressed(MoveUp) || pressed(MoveUpLeft) || pressed(MoveUpRight);
+    input_state.down_pressed = pressed(MoveDown) || pressed(MoveDownLeft) || pressed(MoveDownRight);
+
+    input_state.pass_pressed = pressed(Wait);
+    input_state.descend_pressed = pressed(Descend);
+    input_state.ascend_pressed = pressed(Ascend);
+    input_state.confirm_pressed = pressed(Confirm);
+    input_state.cancel_pressed = pressed(Cancel);
+    input_state.pickup_pressed = pressed(Pickup);
+    input_state.travel_pressed = pressed(Travel);
+    input_state.explore_pressed = pressed(Explore);
+
+    // N uses the Nth thing in the inventory, and shift+N drops it
+    if kb_input.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        input_state.drop_item = just_pressed_digit(&*kb_input);
+    } else {
+        input_state.use_item = just_pressed_digit(&*kb_input);
+    }
+
+    if input_state.up_pressed && input_state.down_pressed {
+        input_state.up_pressed = false;
+        input_state.down_pressed = false;
+    }
+
+    if input_state.left_pressed && input_state.right_pressed {
+        input_state.left_pressed = false;
+        input_state.right_pressed = false;
+    }
+}
+
+/// Which of the number keys 1 through 9 was just pressed, as a 0-based index
+fn just_pressed_digit(kb_input: &Input<KeyCode>) -> Option<usize> {
+    const DIGITS: [KeyCode; 9] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+    ];
+
+    DIGITS.iter().position(|key| kb_input.just_pressed(*key))
+}
+
+pub fn get_save_load_input(
+    kb_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut pending: ResMut<PendingSaveLoad>,
+) {
+    if bindings.just_pressed(&*kb_input, InputAction::QuickSave) {
+        pending.0 = Some(SaveLoadAction::Save);
+    } else if bindings.just_pressed(&*kb_input, InputAction::QuickLoad) {
+        pending.0 = Some(SaveLoadAction::Load);
+    }
+}
+
+pub fn aim_camera(
+    window: Res<WindowDescriptor>,
+    map: Res<Map>,
+    player_query: Query<(&Player, &WorldPos)>,
+    mut camera_query: Query<(&PlayerCamera, &mut Transform)>,
+) {
+    fn get_desired_wp_pt(
+        player_wp_pt: i32,
+        map_min_wp: i32,
+        map_max_wp: i32,
+        window_size_px: f32,
+    ) -> f32 {
+        let window_size_wp = window_size_px / TILE_SIZE;
+
+        // less than this and we have empty space on the left
+        let camera_min_wp = map_min_wp as f32 + (window_size_wp / 2.0) - 0.5;
+
+        // more than this and we have empty space on the right
+        let camera_max_wp = map_max_wp as f32 - (window_size_wp / 2.0) + 0.5;
+
+        // quick check: min_wp >= max_wp iff window_size >= map_width
+        // in which case just center the map
+        let desired_wp = player_wp_pt as f32;
+        if camera_min_wp >= camera_max_wp {
+            (map_min_wp + map_max_wp) as f32 / 2.0
+        } else if desired_wp < camera_min_wp {
+            camera_min_wp
+        } else if desired_wp > camera_max_wp {
+            camera_max_wp
+        } else {
+            desired_wp
+        }
+    }
+
+    // we need the player's position to center the camera
+    let player_wp: WorldPos = match player_query.get_single() {
+        Ok(player) => *player.1,
+        // if no player, just end the system
+        Err(_) => {
+            return;
+        }
+    };
+
+    let bounds = map.bounding_box();
+
+    let desired_x_wp = get_desired_wp_pt(player_wp.x, bounds.x_min, bounds.x_max, window.width);
+    let desired_y_wp = get_desired_wp_pt(player_wp.y, bounds.y_min, bounds.y_max, window.height);
+
+    // aim the camera at the player
+    for (_, mut transform) in camera_query.iter_mut() {
+        let x_dist = desired_x_wp * TILE_SIZE - transform.translation.x;
+        let y_dist = desired_y_wp * TILE_SIZE - transform.translation.y;
+
+        transform.translation.x += x_dist;
+        transform.translation.y += y_dist;
+    }
+}
+
+pub fn world_pos_to_visual_system(mut wp_query: Query<(&WorldPos, &mut Transform)>) {
+    // lock everything to their world position (that is, graphical transform is derived from WP)
+    for (wp, mut transform) in wp_query.iter_mut() {
+        let wp: WorldPos = *wp;
+        transform.translation.x = wp.x as f32 * TILE_SIZE;
+        transform.translation.y = wp.y as f32 * TILE_SIZE;
+    }
+}
+
+/// Make the visible (bevy rendering) component reflect the actual viewing state, if relevant
+pub fn hide_unseen_things(
+    player_query: Query<&Viewshed, (With<Player>,)>,
+    // things without a position (say, in someone's backpack) are never shown
+    mut to_hide_query: Query<(Option<&WorldPos>, &mut Visibility), (With<RequiresSeen>,)>,
+) {
+    let player_vs = match player_query.iter().next() {
+        Some(vs) => vs.visible_tiles.clone(),
+        None => HashSet::new(),
+    };
+
+    for (wp, mut visible) in to_hide_query.iter_mut() {
+        if wp.map_or(false, |wp| player_vs.contains(wp)) {
+            visible.is_visible = true;
+        } else {
+            visible.is_visible = false;
+        }
+    }
+}
+
+pub fn setup_targeting_cursor(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(TILE_SIZE, TILE_SIZE)),
+                ..Default::default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(TargetCursor);
+}
+
+/// Show the targeting cursor where the player is aiming (if they are), colored by whether the
+/// shot would actually get there
+pub fn update_targeting_cursor(
+    targeting: Res<TargetingMode>,
+    map: Res<Map>,
+    player_query: Query<&WorldPos, With<Player>>,
+    mut cursor_query: Query<(&mut Transform, &mut Sprite, &mut Visibility), With<TargetCursor>>,
+) {
+    let cursor = targeting.0;
+    let player_pos = player_query.get_single().ok().copied();
+
+    for (mut transform, mut sprite, mut vis) in cursor_query.iter_mut() {
+        match (cursor, player_pos) {
+            (Some(cursor), Some(player_pos)) => {
+                vis.is_visible = true;
+                // above everything else on the tile
+                *transform = Transform::from_xyz(
+                    cursor.pos.x as f32 * TILE_SIZE,
+                    cursor.pos.y as f32 * TILE_SIZE,
+                    200.0,
+                );
+                sprite.color = if cursor_is_valid(&*map, player_pos, &cursor) {
+                    Color::rgba(1.0, 0.9, 0.2, 0.4)
+                } else {
+                    Color::rgba(1.0, 0.1, 0.1, 0.4)
+                };
+            }
+            _ => {
+                vis.is_visible = false;
+            }
+        }
+    }
+}
+
+/// Give a sprite to anything which wants to be drawn but doesn't have one yet
+pub fn attach_sprites(
+    mut commands: Commands,
+    q: Query<(Entity, &Renderable), Without<TextureAtlasSprite>>,
+    sheet: Res<BasicTilesAtlas>,
+) {
+    for (entity, renderable) in q.iter() {
+        commands
+            .entity(entity)
+            .insert_bundle(make_basic_sprite_bundle(
+                renderable.sprite_index,
+                &sheet.0,
+                renderable.color,
+            ))
+            .insert(Transform::from_xyz(0.0, 0.0, renderable.layer));
+    }
+}
+
+pub fn rebuild_visual_tiles(
+    mut commands: Commands,
+    q: Query<(Entity, &VisualTile)>,
+    map: Res<Map>,
+    sheet: Res<BasicTilesAtlas>,
+) {
+    // the simulation can change the map a zillion times in a frame, but we only rebuild once
+    if !map.is_changed() {
+        return;
+    }
+
+    // wipe out anything previous existing, if any
+    for (e, _) in q.iter() {
+        commands.entity(e).despawn();
+    }
+
+    // then build new tiles
+    for tile_data in map.tiles() {
+        let tile_idx = match tile_data.tile_type {
+            TileType::Wall => 8 * 16 + 3,
+            TileType::Floor => 7 * 16 + 8,
+            TileType::DownStairs | TileType::UpStairs => 7 * 16 + 6,
+        };
+
+        if tile_data.seen {
+            let color = if tile_data.visible {
+                match tile_data.tile_type {
+                    TileType::Floor => Color::rgb(0.4, 0.75, 0.4),
+                    TileType::Wall => Color::rgb(0.8, 0.79, 0.57),
+                    TileType::DownStairs => Color::rgb(0.95, 0.6, 0.3),
+                    TileType::UpStairs => Color::rgb(0.5, 0.7, 0.95),
+                }
+            } else {
+                Color::GRAY
+            };
+            commands
+                .spawn()
+                .insert(VisualTile(tile_data.tile_type))
+                .insert_bundle(make_basic_sprite_bundle(tile_idx, &sheet.0, color))
+                .insert(tile_data.world_pos)
+                // TODO: tile layers?
+                .insert(Transform::default());
+        }
+    }
+}
+
+pub fn update_fps_text(
+    diagnostics: Res<Diagnostics>,
+    kb_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<(&mut Text, Option<&mut Visibility>), With<FpsTextBox>>,
+) {
+    let toggled: bool = bindings.just_pressed(&*kb_input, InputAction::ToggleFps);
+    for (mut text, vis) in query.iter_mut() {
+        if toggled {
+            vis.map(|mut v| v.is_visible = !v.is_visible);
+        }
+        if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
+            if let Some(average) = fps.average() {
+                text.sections[1].value = format!("{:.2}", average);
+            }
+        }
+    }
+}
+
+pub fn update_log_text(
+    logs: Res<Logs>,
+    asset_server: Res<AssetServer>,
+    mut text_component_query: Query<&mut Text, With<LogsTextBox>>,
+) {
+    // no reason rebuilding the component with no changes
+    if !logs.is_changed() {
+        return;
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+
+    for mut text in text_component_query.iter_mut() {
+        text.sections = logs
+            .iter((0..5).rev())
+            .map(|log| TextSection {
+                value: format!("[{}]: {}\n", log.issue_round, log.log.message),
+                style: style.clone(),
+            })
+            .collect();
+    }
+}
+
+pub fn toggle_inventory_panel(
+    kb_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<&mut Visibility, With<InventoryPanel>>,
+) {
+    if bindings.just_pressed(&*kb_input, InputAction::ToggleInventory) {
+        for mut vis in query.iter_mut() {
+            vis.is_visible = !vis.is_visible;
+        }
+    }
+}
+
+pub fn toggle_journal_panel(
+    kb_input: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut query: Query<&mut Visibility, With<JournalPanel>>,
+) {
+    if bindings.just_pressed(&*kb_input, InputAction::ToggleJournal) {
+        for mut vis in query.iter_mut() {
+            vis.is_visible = !vis.is_visible;
+        }
+    }
+}
+
+/// Lists every quest the player has: what to do next for the ones still going, and which ones
+/// are done
+pub fn update_journal_text(
+    asset_server: Res<AssetServer>,
+    quest_log: Res<QuestLog>,
+    registry: Res<QuestRegistry>,
+    mut text_query: Query<&mut Text, With<JournalTextBox>>,
+) {
+    if !quest_log.is_changed() {
+        return;
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+    let done_style = TextStyle {
+        color: Color::GRAY,
+        ..style.clone()
+    };
+
+    let mut lines = vec![("Journal\n".to_string(), style.clone())];
+    for progress in quest_log.0.iter() {
+        let def = match registry.get(&progress.name) {
+            Some(def) => def,
+            None => continue,
+        };
+        match def.stages.get(progress.stage) {
+            Some(stage) => {
+                lines.push((format!("\n{}\n", def.name), style.clone()));
+                lines.push((format!("  {}\n", stage.objective), style.clone()));
+            }
+            None => lines.push((format!("\n{} (done)\n", def.name), done_style.clone())),
+        }
+    }
+    if quest_log.0.is_empty() {
+        lines.push(("\n(nothing yet)\n".to_string(), style));
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.sections = lines
+            .iter()
+            .map(|(line, style)| TextSection {
+                value: line.clone(),
+                style: style.clone(),
+            })
+            .collect();
+    }
+}
+
+/// Shows the conversation panel while there is one, with whatever choices the player has right
+/// now. Those can change as the conversation goes (flags get set, items change hands), so this
+/// keeps up every frame rather than waiting on the dialogue itself to change.
+pub fn update_dialogue_text(
+    asset_server: Res<AssetServer>,
+    active: Res<ActiveDialogue>,
+    registry: Res<DialogueRegistry>,
+    story: StoryState,
+    bindings: Res<KeyBindings>,
+    name_query: Query<&EntityName>,
+    mut panel_query: Query<(&mut Visibility, Option<&mut Text>), With<DialoguePanel>>,
+) {
+    let showing = active.0.is_some();
+    for (mut vis, _) in panel_query.iter_mut() {
+        vis.is_visible = showing;
+    }
+
+    let (state, node) = match active.0.as_ref() {
+        Some(state) => match registry.node(state) {
+            Some(node) => (state, node),
+            None => return,
+        },
+        None => return,
+    };
+    let context = match story.context() {
+        Some(context) => context,
+        None => return,
+    };
+
+    let style = |size: f32| TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: size,
+        color: Color::WHITE,
+    };
+    let speaker = name_query
+        .get(state.speaker)
+        .map(|n| n.0.as_str())
+        .unwrap_or("[unknown]");
+
+    let mut lines = vec![format!("{}\n", speaker), format!("{}\n\n", node.text)];
+    for (idx, choice) in available_choices(&context, node).iter().enumerate() {
+        lines.push(format!("{}. {}\n", idx + 1, choice.text));
+    }
+    lines.push(format!(
+        "\n({} to walk away)",
+        bindings.describe(InputAction::Cancel)
+    ));
+
+    // only the text box itself has any text
+    for (_, text) in panel_query.iter_mut() {
+        let mut text = match text {
+            Some(text) => text,
+            None => continue,
+        };
+        text.sections = lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| TextSection {
+                value: line.clone(),
+                style: style(if idx == 0 { 26.0 } else { 20.0 }),
+            })
+            .collect();
+    }
+}
+
+/// Anything about an item which would show up in the inventory panel
+type ItemChangedFilter = Or<(Changed<Item>, Added<Equipped>)>;
+
+pub fn update_inventory_text(
+    asset_server: Res<AssetServer>,
+    player_query: Query<&Inventory, With<Player>>,
+    changed_inventories: Query<(), (Changed<Inventory>, With<Player>)>,
+    changed_items: Query<(), ItemChangedFilter>,
+    removed_equipped: RemovedComponents<Equipped>,
+    item_query: Query<(&Item, Option<&EntityName>, Option<&Equipped>)>,
+    mut text_component_query: Query<&mut Text, With<InventoryTextBox>>,
+) {
+    // no reason rebuilding the component with no changes
+    if changed_inventories.is_empty()
+        && changed_items.is_empty()
+        && removed_equipped.iter().next().is_none()
+    {
+        return;
+    }
+
+    let style = TextStyle {
+        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+        font_size: 20.0,
+        color: Color::WHITE,
+    };
+
+    let inventory = match player_query.get_single() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+
+    let mut lines = vec!["Inventory (N to use, shift+N to drop)\n".to_string()];
+    let mut total_weight = 0.0;
+    for (idx, item) in inventory.items.iter().enumerate() {
+        if let Ok((item, name, equipped)) = item_query.get(*item) {
+            let worn = if equipped.is_some() {
+                " (equipped)"
+            } else {
+                ""
+            };
+            lines.push(format!(
+                "{}: {}{}\n",
+                idx + 1,
+                describe_item(name, item),
+                worn
+            ));
+            total_weight += item.weight * item.count as f32;
+        }
+    }
+    if inventory.items.is_empty() {
+        lines.push("(nothing)\n".to_string());
+    }
+    lines.push(format!("Weight: {:.1}\n", total_weight));
+
+    for mut text in text_component_query.iter_mut() {
+        text.sections = lines
+            .iter()
+            .map(|line| TextSection {
+                value: line.clone(),
+                style: style.clone(),
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,573 @@
+//! Game data that lives in files under `assets/raws` rather than in code.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use rand::distributions::WeightedIndex;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::components::*;
+use crate::map_builders::MapStyle;
+use crate::resources::DialogueState;
+
+/// Read and parse a raws file. Raws are part of the game, so if they're missing or broken there
+/// is nothing sensible to do but complain loudly.
+fn load_raws<T: DeserializeOwned>(file_name: &str) -> T {
+    // same rule as bevy's asset server, so `cargo run` finds the files
+    let root = std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    let path = root.join("assets").join("raws").join(file_name);
+
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Could not read raws file {}: {}", path.display(), e));
+    ron::from_str(&text)
+        .unwrap_or_else(|e| panic!("Could not parse raws file {}: {}", path.display(), e))
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MonsterDef {
+    /// "{n}" gets replaced with a number, so monsters can be told apart
+    pub name: String,
+    /// Index into the basic tiles sheet
+    pub glyph: usize,
+    pub color: (f32, f32, f32),
+    pub stats: MonsterStatsDef,
+    pub viewshed_range: i32,
+    /// How it acts; a plain chase-and-wander if not specified
+    #[serde(default = "default_behaviors")]
+    pub behaviors: Vec<Behavior>,
+    /// Normal speed (same as the player's) if not specified
+    #[serde(default = "default_speed")]
+    pub speed: u32,
+    /// Name of its faction in `factions.ron`
+    pub faction: String,
+    /// Status effects it puts on whoever it hits, if any
+    #[serde(default)]
+    pub on_hit: Vec<StatusEffect>,
+    pub spawn_weight: u32,
+    pub min_depth: u32,
+}
+
+fn default_behaviors() -> Vec<Behavior> {
+    vec![Behavior::Chase, Behavior::Wander { chance: 0.25 }]
+}
+
+fn default_speed() -> u32 {
+    Speed::NORMAL.0
+}
+
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct MonsterStatsDef {
+    pub max_hp: i32,
+    pub defense: i32,
+    pub power: i32,
+}
+
+impl MonsterDef {
+    pub fn make_name(&self, n: usize) -> EntityName {
+        EntityName(self.name.replace("{n}", &n.to_string()))
+    }
+
+    pub fn make_renderable(&self) -> Renderable {
+        let (r, g, b) = self.color;
+        Renderable {
+            sprite_index: self.glyph,
+            color: Color::rgb(r, g, b),
+            layer: 40.0,
+        }
+    }
+
+    pub fn make_stats(&self) -> CombatStats {
+        CombatStats {
+            max_hp: self.stats.max_hp,
+            hp: self.stats.max_hp,
+            defense: self.stats.defense,
+            power: self.stats.power,
+        }
+    }
+
+    pub fn make_behaviors(&self, home: WorldPos) -> AiBehaviors {
+        AiBehaviors {
+            behaviors: self.behaviors.clone(),
+            home,
+        }
+    }
+
+    pub fn make_speed(&self) -> Speed {
+        Speed(self.speed)
+    }
+
+    pub fn make_faction(&self) -> Faction {
+        Faction(self.faction.clone())
+    }
+
+    pub fn make_on_hit(&self) -> Option<InflictsOnHit> {
+        if self.on_hit.is_empty() {
+            None
+        } else {
+            Some(InflictsOnHit(self.on_hit.clone()))
+        }
+    }
+
+    pub fn make_viewshed(&self) -> Viewshed {
+        Viewshed {
+            range: self.viewshed_range,
+            ..Viewshed::new()
+        }
+    }
+}
+
+/// Every monster the game knows about, as read from `monsters.ron`
+#[derive(Clone, Debug)]
+pub struct MonsterRegistry {
+    monsters: Vec<MonsterDef>,
+}
+
+impl MonsterRegistry {
+    /// Weighted table of the monsters allowed at the given depth, or None if there aren't any.
+    pub fn spawn_table(&self, depth: u32) -> Option<SpawnTable<'_, MonsterDef>> {
+        SpawnTable::new(&self.monsters, depth, |m| (m.spawn_weight, m.min_depth))
+    }
+}
+
+pub struct SpawnTable<'a, T> {
+    allowed: Vec<&'a T>,
+    weights: WeightedIndex<u32>,
+}
+
+impl<'a, T> SpawnTable<'a, T> {
+    /// Everything allowed at the given depth; `weight_and_depth` gives the spawn weight and the
+    /// shallowest depth for each def.
+    fn new<F>(defs: &'a [T], depth: u32, weight_and_depth: F) -> Option<Self>
+    where
+        F: Fn(&T) -> (u32, u32),
+    {
+        let allowed: Vec<&T> = defs
+            .iter()
+            .filter(|def| {
+                let (weight, min_depth) = weight_and_depth(def);
+                min_depth <= depth && weight > 0
+            })
+            .collect();
+
+        let weights = WeightedIndex::new(allowed.iter().map(|def| weight_and_depth(def).0)).ok()?;
+
+        Some(SpawnTable { allowed, weights })
+    }
+
+    pub fn roll<R: rand::Rng>(&self, rng: &mut R) -> &'a T {
+        self.allowed[rng.sample(&self.weights)]
+    }
+}
+
+pub fn load_monster_registry(mut commands: Commands) {
+    let monsters: Vec<MonsterDef> = load_raws("monsters.ron");
+    commands.insert_resource(MonsterRegistry { monsters });
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ItemDef {
+    pub name: String,
+    /// Index into the basic tiles sheet
+    pub glyph: usize,
+    pub color: (f32, f32, f32),
+    /// Weight of a single one
+    pub weight: f32,
+    /// How many show up together, at least and at most
+    #[serde(default = "ItemDef::single")]
+    pub stack: (u32, u32),
+    /// What happens when it's used, if it can be
+    #[serde(default)]
+    pub consumable: Option<Consumable>,
+    /// Where it's worn and what it does, if it can be
+    #[serde(default)]
+    pub equippable: Option<Equippable>,
+    pub spawn_weight: u32,
+    pub min_depth: u32,
+}
+
+impl ItemDef {
+    fn single() -> (u32, u32) {
+        (1, 1)
+    }
+
+    pub fn make_name(&self) -> EntityName {
+        EntityName(self.name.clone())
+    }
+
+    pub fn make_renderable(&self) -> Renderable {
+        let (r, g, b) = self.color;
+        Renderable {
+            sprite_index: self.glyph,
+            color: Color::rgb(r, g, b),
+            layer: 20.0,
+        }
+    }
+
+    pub fn make_item<R: rand::Rng>(&self, rng: &mut R) -> Item {
+        let (min, max) = self.stack;
+        Item {
+            weight: self.weight,
+            count: rng.gen_range(min..=max.max(min)),
+        }
+    }
+}
+
+/// Every item the game knows about, as read from `items.ron`
+#[derive(Clone, Debug)]
+pub struct ItemRegistry {
+    items: Vec<ItemDef>,
+}
+
+impl ItemRegistry {
+    pub fn get(&self, name: &str) -> Option<&ItemDef> {
+        self.items.iter().find(|def| def.name == name)
+    }
+
+    /// Weighted table of the items allowed at the given depth, or None if there aren't any.
+    pub fn spawn_table(&self, depth: u32) -> Option<SpawnTable<'_, ItemDef>> {
+        SpawnTable::new(&self.items, depth, |i| (i.spawn_weight, i.min_depth))
+    }
+}
+
+pub fn load_item_registry(mut commands: Commands) {
+    let items: Vec<ItemDef> = load_raws("items.ron");
+    commands.insert_resource(ItemRegistry { items });
+}
+
+/// One way a level can be laid out, and which depths it's allowed at
+#[derive(Clone, Debug, Deserialize)]
+pub struct LevelStyleDef {
+    pub style: MapStyle,
+    /// Whether to stamp a prefab vault on top of the base layout
+    #[serde(default)]
+    pub vaults: bool,
+    pub weight: u32,
+    pub min_depth: u32,
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+}
+
+/// Every level style the game knows about, as read from `levels.ron`
+#[derive(Clone, Debug)]
+pub struct LevelStyleRegistry {
+    styles: Vec<LevelStyleDef>,
+}
+
+impl LevelStyleRegistry {
+    /// Pick a style for the given depth. If nothing is allowed that deep, falls back to plain rooms.
+    pub fn pick<R: rand::Rng>(&self, depth: u32, rng: &mut R) -> (MapStyle, bool) {
+        let allowed: Vec<&LevelStyleDef> = self
+            .styles
+            .iter()
+            .filter(|s| {
+                s.min_depth <= depth && s.max_depth.map_or(true, |max| depth <= max) && s.weight > 0
+            })
+            .collect();
+
+        match WeightedIndex::new(allowed.iter().map(|s| s.weight)) {
+            Ok(weights) => {
+                let def = allowed[rng.sample(&weights)];
+                (def.style, def.vaults)
+            }
+            Err(_) => (MapStyle::SimpleRooms, false),
+        }
+    }
+}
+
+pub fn load_level_style_registry(mut commands: Commands) {
+    let styles: Vec<LevelStyleDef> = load_raws("levels.ron");
+    commands.insert_resource(LevelStyleRegistry { styles });
+}
+
+/// Someone who isn't out to kill the player, at least to begin with. Each one shows up once, on
+/// the level they belong to.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NpcDef {
+    pub name: String,
+    /// Index into the basic tiles sheet
+    pub glyph: usize,
+    pub color: (f32, f32, f32),
+    /// Only matters if it comes to a fight
+    pub stats: MonsterStatsDef,
+    pub viewshed_range: i32,
+    /// Name of their conversation in `dialogue.ron`
+    pub dialogue: String,
+    /// Name of their faction in `factions.ron`
+    pub faction: String,
+    pub depth: u32,
+}
+
+impl NpcDef {
+    pub fn make_name(&self) -> EntityName {
+        EntityName(self.name.clone())
+    }
+
+    pub fn make_renderable(&self) -> Renderable {
+        let (r, g, b) = self.color;
+        Renderable {
+            sprite_index: self.glyph,
+            color: Color::rgb(r, g, b),
+            layer: 40.0,
+        }
+    }
+
+    pub fn make_stats(&self) -> CombatStats {
+        CombatStats {
+            max_hp: self.stats.max_hp,
+            hp: self.stats.max_hp,
+            defense: self.stats.defense,
+            power: self.stats.power,
+        }
+    }
+
+    pub fn make_faction(&self) -> Faction {
+        Faction(self.faction.clone())
+    }
+
+    pub fn make_talker(&self) -> Talker {
+        Talker {
+            dialogue: self.dialogue.clone(),
+        }
+    }
+
+    pub fn make_viewshed(&self) -> Viewshed {
+        Viewshed {
+            range: self.viewshed_range,
+            ..Viewshed::new()
+        }
+    }
+}
+
+/// Every NPC the game knows about, as read from `npcs.ron`
+#[derive(Clone, Debug)]
+pub struct NpcRegistry {
+    npcs: Vec<NpcDef>,
+}
+
+impl NpcRegistry {
+    pub fn on_depth(&self, depth: u32) -> impl Iterator<Item = &NpcDef> {
+        self.npcs.iter().filter(move |npc| npc.depth == depth)
+    }
+}
+
+pub fn load_npc_registry(mut commands: Commands) {
+    let npcs: Vec<NpcDef> = load_raws("npcs.ron");
+    commands.insert_resource(NpcRegistry { npcs });
+}
+
+/// A whole conversation: a set of named nodes, each saying something and offering choices that
+/// lead to other nodes
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueDef {
+    pub name: String,
+    /// The node every conversation starts at
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueNode {
+    pub text: String,
+    pub choices: Vec<DialogueChoice>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogueChoice {
+    pub text: String,
+    /// All of these have to hold for the choice to be offered at all
+    #[serde(default)]
+    pub conditions: Vec<StoryCondition>,
+    /// What happens when it's picked, in order
+    #[serde(default)]
+    pub outcomes: Vec<DialogueOutcome>,
+    /// The node to go to next; the conversation is over if there isn't one
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Every conversation the game knows about, as read from `dialogue.ron`
+#[derive(Clone, Debug)]
+pub struct DialogueRegistry {
+    dialogues: HashMap<String, DialogueDef>,
+}
+
+impl DialogueRegistry {
+    pub fn get(&self, name: &str) -> Option<&DialogueDef> {
+        self.dialogues.get(name)
+    }
+
+    /// Wherever the given conversation is at right now
+    pub fn node(&self, state: &DialogueState) -> Option<&DialogueNode> {
+        self.get(&state.dialogue)
+            .and_then(|def| def.nodes.get(&state.node))
+    }
+}
+
+pub fn load_dialogue_registry(mut commands: Commands) {
+    let defs: Vec<DialogueDef> = load_raws("dialogue.ron");
+
+    // a choice leading nowhere would leave the player stuck mid-conversation, so check up front
+    for def in defs.iter() {
+        let targets = def
+            .nodes
+            .values()
+            .flat_map(|node| node.choices.iter())
+            .filter_map(|c| c.next.as_ref());
+        for target in std::iter::once(&def.start).chain(targets) {
+            if !def.nodes.contains_key(target) {
+                panic!(
+                    "Dialogue {} refers to a node {} which doesn't exist",
+                    def.name, target
+                );
+            }
+        }
+    }
+
+    let dialogues = defs
+        .into_iter()
+        .map(|def| (def.name.clone(), def))
+        .collect();
+    commands.insert_resource(DialogueRegistry { dialogues });
+}
+
+/// A quest: a list of stages, each finished once all its conditions have been met
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuestDef {
+    pub name: String,
+    /// The quest is given to the player as soon as all of these hold; with none, it's given
+    /// right at the start of the game
+    #[serde(default)]
+    pub starts_when: Vec<StoryCondition>,
+    pub stages: Vec<QuestStage>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct QuestStage {
+    /// What the journal says to do
+    pub objective: String,
+    pub conditions: Vec<QuestCondition>,
+    /// Story flags set once this stage is done, so dialogue and later quests can tell
+    #[serde(default)]
+    pub sets_flags: Vec<String>,
+}
+
+/// Every quest the game knows about, as read from `quests.ron`, in order
+#[derive(Clone, Debug)]
+pub struct QuestRegistry {
+    pub quests: Vec<QuestDef>,
+}
+
+impl QuestRegistry {
+    pub fn get(&self, name: &str) -> Option<&QuestDef> {
+        self.quests.iter().find(|q| q.name == name)
+    }
+}
+
+pub fn load_quest_registry(mut commands: Commands) {
+    let quests: Vec<QuestDef> = load_raws("quests.ron");
+
+    // progress is saved by name, so two quests with the same one would get mixed up
+    for (idx, quest) in quests.iter().enumerate() {
+        if quests[..idx].iter().any(|q| q.name == quest.name) {
+            panic!("Quest {} is defined more than once", quest.name);
+        }
+        if quest.stages.is_empty() {
+            panic!("Quest {} has no stages", quest.name);
+        }
+    }
+
+    commands.insert_resource(QuestRegistry { quests });
+}
+
+/// A way the game can end, other than dying
+#[derive(Clone, Debug, Deserialize)]
+pub struct EndingDef {
+    pub name: String,
+    /// What happened; shown on the way out
+    pub text: String,
+    /// All of these have to hold
+    pub conditions: Vec<StoryCondition>,
+}
+
+/// Every ending, as read from `endings.ron`; when more than one could happen, the first wins
+#[derive(Clone, Debug)]
+pub struct EndingRegistry {
+    pub endings: Vec<EndingDef>,
+}
+
+pub fn load_ending_registry(mut commands: Commands) {
+    let endings: Vec<EndingDef> = load_raws("endings.ron");
+
+    // an ending with no conditions would end the game before it started
+    for ending in endings.iter() {
+        if ending.conditions.is_empty() {
+            panic!("Ending {} has no conditions", ending.name);
+        }
+    }
+
+    commands.insert_resource(EndingRegistry { endings });
+}
+
+/// The faction everyone else's feelings about the player are listed under in `factions.ron`
+pub const PLAYER_FACTION: &str = "Player";
+
+/// A side that monsters and NPCs can be on
+#[derive(Clone, Debug, Deserialize)]
+pub struct FactionDef {
+    pub name: String,
+    /// How this faction feels about the others (and about "Player"); anything not listed is
+    /// neutral
+    #[serde(default)]
+    pub reactions: HashMap<String, Reaction>,
+}
+
+/// Every faction, as read from `factions.ron`
+#[derive(Clone, Debug)]
+pub struct FactionRegistry {
+    factions: HashMap<String, FactionDef>,
+}
+
+impl FactionRegistry {
+    /// How `from` feels about `to` before anything the player has done; everyone gets along with
+    /// their own side
+    pub fn reaction(&self, from: &str, to: &str) -> Reaction {
+        if from == to {
+            return Reaction::Friendly;
+        }
+        self.factions
+            .get(from)
+            .and_then(|def| def.reactions.get(to))
+            .copied()
+            .unwrap_or(Reaction::Neutral)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factions.keys().map(|name| name.as_str())
+    }
+}
+
+pub fn load_faction_registry(mut commands: Commands) {
+    let defs: Vec<FactionDef> = load_raws("factions.ron");
+
+    // a typo here would quietly make two factions neutral to each other, so check up front
+    for def in defs.iter() {
+        for other in def.reactions.keys() {
+            if other != PLAYER_FACTION && !defs.iter().any(|d| d.name == *other) {
+                panic!(
+                    "Faction {} has a reaction to {}, which doesn't exist",
+                    def.name, other
+                );
+            }
+        }
+    }
+
+    let factions = defs
+        .into_iter()
+        .map(|def| (def.name.clone(), def))
+        .collect();
+    commands.insert_resource(FactionRegistry { factions });
+}
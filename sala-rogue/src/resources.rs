@@ -1,8 +1,13 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::path::PathBuf;
 
 use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
 
 use crate::components::*;
 
@@ -12,6 +17,183 @@ pub mod events;
 #[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct CurrentTurnNumber(pub usize);
 
+/// Seed for everything random about world generation; the same seed always makes the same dungeon.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GameSeed(pub u64);
+
+/// Where everything random about how the game plays out comes from (who wanders where, who
+/// stumbles), as opposed to how levels are laid out. It starts from the game's seed, so a seeded
+/// game plays out the same way every time, and saving picks a new seed to carry on from.
+#[derive(Clone, Debug)]
+pub struct GameRng(pub StdRng);
+
+impl GameRng {
+    pub fn from_seed(seed: u64) -> Self {
+        GameRng(StdRng::seed_from_u64(seed))
+    }
+}
+
+/// Seed from the command line, if any. Every new game uses it, so the same dungeon comes up
+/// each time; otherwise each new game gets a random one.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FixedSeed(pub Option<u64>);
+
+/// Options for how the game plays, which can be changed mid-game
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GameSettings {
+    /// Whether everything (the player and monsters alike) can step diagonally
+    pub diagonal_movement: bool,
+}
+
+/// Which screen the game is on. Turns are only taken InGame.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum GameState {
+    MainMenu,
+    InGame,
+    Paused,
+    /// Rebinding keys, which is only reachable from the pause screen
+    KeyBindings,
+    GameOver,
+}
+
+/// Named facts about how the story has gone so far; set and checked by dialogue and quests.
+/// Every flag is really a counter: setting one makes it 1, and one that isn't there is 0.
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StoryFlags(pub BTreeMap<String, i32>);
+
+impl StoryFlags {
+    pub fn get(&self, flag: &str) -> i32 {
+        self.0.get(flag).copied().unwrap_or(0)
+    }
+
+    pub fn is_set(&self, flag: &str) -> bool {
+        self.get(flag) != 0
+    }
+
+    /// Setting a flag that's already been counted past 1 leaves the count alone
+    pub fn set(&mut self, flag: &str) {
+        self.0.entry(flag.to_string()).or_insert(1);
+    }
+
+    pub fn clear(&mut self, flag: &str) {
+        self.0.remove(flag);
+    }
+
+    pub fn add(&mut self, counter: &str, amount: i32) {
+        let value = self.get(counter) + amount;
+        if value == 0 {
+            self.0.remove(counter);
+        } else {
+            self.0.insert(counter.to_string(), value);
+        }
+    }
+}
+
+/// How well each faction thinks of the player, by faction name; anything not listed is 0
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FactionStandings(pub BTreeMap<String, i32>);
+
+impl FactionStandings {
+    pub fn get(&self, faction: &str) -> i32 {
+        self.0.get(faction).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, faction: &str, amount: i32) {
+        *self.0.entry(faction.to_string()).or_insert(0) += amount;
+    }
+}
+
+/// Every quest the player has been given, in the order they got them
+#[derive(Default, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct QuestLog(pub Vec<QuestProgress>);
+
+impl QuestLog {
+    pub fn get(&self, name: &str) -> Option<&QuestProgress> {
+        self.0.iter().find(|q| q.name == name)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct QuestProgress {
+    /// Name of the quest in `quests.ron`
+    pub name: String,
+    /// Index into the quest's stages; once it's past the last one, the quest is done
+    pub stage: usize,
+    /// How much of each of the current stage's conditions has happened so far, in order; only
+    /// the ones about things happening use this
+    pub counts: Vec<u32>,
+}
+
+/// The conversation the player is in the middle of, if any. Nothing else happens until it's over.
+#[derive(Default, Clone, Eq, PartialEq, Debug)]
+pub struct ActiveDialogue(pub Option<DialogueState>);
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DialogueState {
+    pub speaker: Entity,
+    /// Name of the conversation in `dialogue.ron`
+    pub dialogue: String,
+    /// Which part of it we're at
+    pub node: String,
+}
+
+/// Where the player is on the key bindings screen
+#[derive(Default, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct KeyBindingsMenu {
+    /// Index into `InputAction::ALL`
+    pub selected: usize,
+    /// Waiting for the next key press to bind to the selected action; `Some(true)` means it
+    /// replaces the old keys, `Some(false)` means it's added alongside them
+    pub capturing: Option<bool>,
+    /// Whatever went wrong last, if anything
+    pub message: Option<String>,
+}
+
+/// How the last game ended; only exists once the player has died or reached an ending
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GameOverInfo {
+    pub turn: usize,
+    pub depth: u32,
+    /// How they died, or what the ending says happened
+    pub cause: String,
+    /// Which ending was reached (see `endings.ron`); `None` means the player died
+    pub ending: Option<String>,
+}
+
+/// Where the game gets saved to, and loaded from
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SaveFilePath(pub PathBuf);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SaveLoadAction {
+    Save,
+    Load,
+    /// Throw away the current game and start over from a fresh dungeon
+    NewGame,
+}
+
+/// A save, load or restart that should happen before the next turn is processed
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PendingSaveLoad(pub Option<SaveLoadAction>);
+
+/// How far down the dungeon the player is; the first level is depth 1
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CurrentDepth(pub u32);
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum StairsDirection {
+    Up,
+    Down,
+}
+
+/// Set when the player takes the stairs; the level is swapped out at the end of the turn
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PendingLevelChange(pub Option<StairsDirection>);
+
+/// How many turns a headless run is allowed to go before quitting
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TurnLimit(pub usize);
+
 /// Indicates the player system has already run once this frame, which is used for various things
 #[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct PlayerMovedInFrame(pub bool);
@@ -28,9 +210,67 @@ pub struct PlayerInputState {
     pub right_pressed: bool,
     pub left_pressed: bool,
     pub pass_pressed: bool,
+    pub descend_pressed: bool,
+    pub ascend_pressed: bool,
+    pub pickup_pressed: bool,
+    /// Index into the player's inventory
+    pub drop_item: Option<usize>,
+    /// Index into the player's inventory
+    pub use_item: Option<usize>,
+    pub confirm_pressed: bool,
+    pub cancel_pressed: bool,
+    pub travel_pressed: bool,
+    pub explore_pressed: bool,
 }
 
-#[derive(Default, Debug)]
+/// What the player is picking a tile for
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum TargetPurpose {
+    UseItem {
+        item: Entity,
+    },
+    /// Somewhere to walk to; any seen tile will do
+    Travel,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TargetingCursor {
+    pub purpose: TargetPurpose,
+    pub pos: WorldPos,
+    /// How far from the player the cursor can go
+    pub range: i32,
+}
+
+/// Set while the player is picking a tile. While it is, the movement keys move the cursor instead
+/// of the player, and nothing happens until the target is confirmed or the whole thing cancelled.
+#[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TargetingMode(pub Option<TargetingCursor>);
+
+/// Something the player keeps doing, a turn at a time, without a key press for each turn
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AutoMoveKind {
+    Travel {
+        destination: WorldPos,
+    },
+    /// Head for the nearest place the player hasn't seen, until there aren't any
+    Explore,
+}
+
+/// What the player is doing on their own, if anything. Any key press stops it, and so does
+/// anything interesting happening.
+#[derive(Default, Clone, Debug)]
+pub struct AutoMove {
+    pub current: Option<AutoMoveKind>,
+    /// Monsters the player could see as of the last turn; only newcomers are interesting
+    pub visible_monsters: HashSet<Entity>,
+    /// Items already pointed out while exploring, so each one only stops it once
+    pub noticed_items: HashSet<Entity>,
+    /// Everywhere exploring has already taken the player; there's no point going back just
+    /// because something next to it still hasn't been seen
+    pub explored_from: HashSet<WorldPos>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct Logs {
     /// logs[0] is the newest
     logs: VecDeque<LogInfo>,
@@ -46,60 +286,108 @@ impl Logs {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LogInfo {
     pub log: Log,
     pub issue_round: usize,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Log {
     pub message: String,
 }
 
+/// Everything gets a turn -- players, mobs, environmental effects, whatever. Everyone builds up
+/// energy at their own speed and acts once they have enough; the action then costs them some,
+/// depending on what it was. Rather than counting energy up tick by tick, this keeps track of
+/// when each one will next be ready, which comes to the same thing.
 #[derive(Default, Debug)]
 pub struct TurnOrder {
-    /// Everything gets a turn -- players, mobs, environmental effects, whatever
-    /// And everything goes in order
-    turn_order: VecDeque<Entity>,
+    actors: Vec<ScheduledActor>,
+    /// Ties go to whoever has been waiting the longest
+    next_sequence: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ScheduledActor {
+    entity: Entity,
+    ready_at: u64,
+    sequence: u64,
+}
+
+impl ScheduledActor {
+    fn order(&self) -> (u64, u64) {
+        (self.ready_at, self.sequence)
+    }
 }
 
 impl TurnOrder {
     pub fn current_holder(&self) -> Option<Entity> {
-        self.turn_order.get(0).copied()
+        self.actors
+            .iter()
+            .min_by_key(|actor| actor.order())
+            .map(|actor| actor.entity)
     }
 
-    pub fn end_turn(&mut self) {
-        if self.turn_order.len() <= 1 {
-            return;
+    /// Whenever whoever's up is ready to go
+    pub fn now(&self) -> u64 {
+        self.actors
+            .iter()
+            .map(|actor| actor.ready_at)
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The entity is done, and can go again after the given time has passed
+    pub fn end_turn(&mut self, entity: Entity, time_taken: u64) {
+        let sequence = self.take_sequence();
+        if let Some(actor) = self.actors.iter_mut().find(|a| a.entity == entity) {
+            actor.ready_at += time_taken;
+            actor.sequence = sequence;
         }
-        self.turn_order.rotate_left(1);
     }
 
+    /// Everything in the turn order, starting with whoever is up now, with when they're ready
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, u64)> {
+        let mut actors = self.actors.clone();
+        actors.sort_by_key(|actor| actor.order());
+        actors
+            .into_iter()
+            .map(|actor| (actor.entity, actor.ready_at))
+    }
+
+    /// Newcomers are ready right away, but go after everyone else who is
     pub fn add_if_not_present(&mut self, entity: Entity) {
-        let exists = self.turn_order.iter().copied().any(|e| e == entity);
-        if !exists {
-            self.turn_order.push_back(entity);
+        let now = self.now();
+        self.add_at(entity, now);
+    }
+
+    /// For putting back a turn order as it was; these should be added in the order they go in
+    pub fn add_at(&mut self, entity: Entity, ready_at: u64) {
+        if self.actors.iter().any(|actor| actor.entity == entity) {
+            return;
         }
+        let sequence = self.take_sequence();
+        self.actors.push(ScheduledActor {
+            entity,
+            ready_at,
+            sequence,
+        });
     }
 
     pub fn remove_from_turn_order(&mut self, entity: Entity) {
-        let mut i = 0;
-        while i < self.turn_order.len() {
-            if Some(entity) == self.turn_order.get(i).copied() {
-                break;
-            }
-            i += 1;
-        }
+        self.actors.retain(|actor| actor.entity != entity);
+    }
 
-        if i < self.turn_order.len() {
-            self.turn_order.remove(i);
-        }
+    fn take_sequence(&mut self) -> u64 {
+        let out = self.next_sequence;
+        self.next_sequence += 1;
+        out
     }
 
     #[allow(dead_code)] // used for debug stuff
     pub fn len(&self) -> usize {
-        self.turn_order.len()
+        self.actors.len()
     }
 }
 
@@ -132,6 +420,14 @@ impl CacheMap {
         self.0.get(&wp).map(|s| !s.is_empty()).unwrap_or(false)
     }
 
+    /// Everything on the tile, in no particular order
+    pub fn get_all(&self, wp: WorldPos) -> impl Iterator<Item = Entity> + '_ {
+        self.0
+            .get(&wp)
+            .into_iter()
+            .flat_map(|set| set.iter().copied())
+    }
+
     pub fn get_any(&self, wp: WorldPos) -> Option<Entity> {
         match self.0.get(&wp) {
             Some(set) => set.iter().copied().next(),
@@ -179,5 +475,125 @@ impl std::ops::DerefMut for CombatStatsTiles {
 
 pub type DijkstraMap = HashMap<WorldPos, i32>;
 
-#[derive(Default, Clone, Debug)]
-pub struct PlayerDistanceMap(pub DijkstraMap);
+/// The things the AI can steer toward (or away from), each with its own Dijkstra map
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum DijkstraGoal {
+    TowardPlayer,
+    /// Not just "away from the player"; this one knows running into a dead end is a bad idea
+    FleePlayer,
+    /// Toward anything lying around that could be picked up
+    TowardItems,
+    /// Toward the nearest friend of whoever is asking, not counting themselves
+    TowardAllies,
+}
+
+impl DijkstraGoal {
+    pub const ALL: [DijkstraGoal; 4] = [
+        DijkstraGoal::TowardPlayer,
+        DijkstraGoal::FleePlayer,
+        DijkstraGoal::TowardItems,
+        DijkstraGoal::TowardAllies,
+    ];
+
+    /// Whether everybody shares the one map for this goal. Who counts as an ally depends on who's
+    /// asking, so that one has a map per faction instead.
+    pub fn is_shared(self) -> bool {
+        self != DijkstraGoal::TowardAllies
+    }
+}
+
+/// Distance from each tile to the nearest two members of some group, along with who those are,
+/// so any member can ask how far it is to the nearest *other* member
+pub type NearestTwoMap = HashMap<WorldPos, Vec<(i32, Entity)>>;
+
+/// How far it is from this tile to the nearest member of the group who isn't `asker`
+pub fn nearest_other(map: &NearestTwoMap, wp: WorldPos, asker: Entity) -> Option<i32> {
+    map.get(&wp)?
+        .iter()
+        .find(|(_, member)| *member != asker)
+        .map(|(dist, _)| *dist)
+}
+
+/// Every Dijkstra map the AI can steer by. Anything that could change one marks it dirty, and
+/// it gets rebuilt before anyone acts again; a fresh one is all dirty.
+///
+/// Maps toward each faction's allies are different: hardly anybody needs one, so they're only
+/// built when asked for (see `build_allies`), and just thrown away when they go out of date.
+#[derive(Clone, Debug)]
+pub struct DijkstraMaps {
+    maps: HashMap<DijkstraGoal, DijkstraMap>,
+    dirty: HashSet<DijkstraGoal>,
+    allies: HashMap<String, NearestTwoMap>,
+}
+
+impl Default for DijkstraMaps {
+    fn default() -> Self {
+        DijkstraMaps {
+            maps: HashMap::new(),
+            dirty: DijkstraGoal::ALL
+                .iter()
+                .copied()
+                .filter(|goal| goal.is_shared())
+                .collect(),
+            allies: HashMap::new(),
+        }
+    }
+}
+
+impl DijkstraMaps {
+    pub fn get(&self, goal: DijkstraGoal) -> Option<&DijkstraMap> {
+        self.maps.get(&goal)
+    }
+
+    pub fn distance(&self, goal: DijkstraGoal, wp: WorldPos) -> Option<i32> {
+        self.maps.get(&goal).and_then(|m| m.get(&wp)).copied()
+    }
+
+    pub fn set(&mut self, goal: DijkstraGoal, map: DijkstraMap) {
+        self.maps.insert(goal, map);
+        self.dirty.remove(&goal);
+    }
+
+    pub fn mark_dirty(&mut self, goal: DijkstraGoal) {
+        if goal.is_shared() {
+            self.dirty.insert(goal);
+        } else {
+            self.allies.clear();
+        }
+    }
+
+    pub fn take_dirty(&mut self) -> Vec<DijkstraGoal> {
+        self.dirty.drain().collect()
+    }
+
+    /// Makes sure there's a `TowardAllies` map for this faction (toward everyone on friendly
+    /// terms with it), building it with `build` if there isn't
+    pub fn build_allies<F: FnOnce() -> NearestTwoMap>(&mut self, faction: &str, build: F) {
+        self.allies.entry(faction.to_string()).or_insert_with(build);
+    }
+
+    /// Weighted sum of the given maps at this tile, as seen by `asker` (who's in `faction`, if
+    /// any); lower is more attractive. None if the tile isn't reachable in one of the shared
+    /// maps. Allies with no map built yet, or nobody to count as one, don't pull either way.
+    pub fn combined_cost(
+        &self,
+        weights: &[(DijkstraGoal, f32)],
+        wp: WorldPos,
+        asker: Entity,
+        faction: Option<&str>,
+    ) -> Option<f32> {
+        weights
+            .iter()
+            .filter(|(_, weight)| *weight != 0.0)
+            .map(|(goal, weight)| match goal {
+                DijkstraGoal::TowardAllies => Some(
+                    faction
+                        .and_then(|faction| self.allies.get(faction))
+                        .and_then(|allies| nearest_other(allies, wp, asker))
+                        .map_or(0.0, |d| d as f32 * weight),
+                ),
+                _ => self.distance(*goal, wp).map(|d| d as f32 * weight),
+            })
+            .sum()
+    }
+}
@@ -1,23 +1,50 @@
-use std::collections::HashSet;
-
-use bevy::diagnostic::Diagnostics;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use rand::seq::IteratorRandom;
 
-use crate::bevy_util::make_basic_sprite_bundle;
 use crate::components::*;
-use crate::map::{Map, TileType, TILE_SIZE};
+use crate::levels;
+use crate::map::{Map, TileType};
 use crate::resources::*;
-use crate::{AppExtension, FrameTimeDiagnosticsPlugin};
+use crate::AppExtension;
 
+mod auto_move;
+mod dialogue;
 mod dijkstra;
+mod discovery;
+mod equipment;
+mod factions;
 mod fov;
-
+mod items;
+mod monster_ai;
+mod pathfinding;
+mod status;
+mod story;
+mod targeting;
+
+pub use auto_move::{auto_move_input, track_auto_move};
+pub use dialogue::{
+    available_choices, end_dialogue_on_death, handle_dialogue_input, start_dialogue,
+};
+pub use dijkstra::{
+    distance_dijkstra_map, invalidate_dijkstra_maps, seeded_dijkstra_map, update_dijkstra_maps,
+};
+pub use discovery::{announce_landmarks, track_first_touched};
+pub use equipment::{effective_stats, process_equipment_events};
+pub use factions::{update_reputation, Reactions};
 pub use fov::{compute_viewsheds, update_map_visibility};
+pub use items::{
+    describe_item, process_item_drop, process_item_gifts, process_item_pickup, process_item_use,
+};
+pub use monster_ai::{monster_ai, turn_hostile};
+pub use pathfinding::{a_star, find_path};
+pub use status::{apply_status_effects, tick_status_effects};
+pub use story::{check_endings, update_quests, update_story_flags, StoryContext, StoryState};
+pub use targeting::{cursor_is_valid, has_line_of_fire, line_between};
 
 pub fn world_tick(world: &mut World) {
     // This is done once at the top of the tick, not inside the loop
     let mut input_state = SystemStage::single_threaded();
-    input_state.add_system(get_player_input);
     input_state.add_system(clear_player_moved_in_frame);
     input_state.run(world);
 
@@ -28,26 +55,53 @@ pub fn world_tick(world: &mut World) {
     // TODO: is there a reason we might want to keep this SystemStage in some kind of cached / stored place in the app? i guess profile?
     full_stage
         // first, make sure turn order and map registration are set up correctly
+        .add_sequential_system(&mut system_idx, apply_movement_setting)
         .add_sequential_system(&mut system_idx, assign_turn_order)
         .add_sequential_system(&mut system_idx, clear_turn_order_requests)
         .add_sequential_system(&mut system_idx, assign_block_map_indexing)
         .add_sequential_system(&mut system_idx, assign_combat_map_indexing)
         .add_sequential_system(&mut system_idx, clear_indexing_requests)
+        .add_sequential_system(&mut system_idx, update_dijkstra_maps)
         // then let thinking agents take their turns
+        .add_sequential_system(&mut system_idx, handle_dialogue_input)
         .add_sequential_system(&mut system_idx, handle_input)
+        .add_sequential_system(&mut system_idx, start_dialogue)
         .add_sequential_system(&mut system_idx, monster_ai)
         .add_sequential_system(&mut system_idx, handle_end_of_turn)
+        .add_sequential_system(&mut system_idx, tick_status_effects)
+        .add_sequential_system(&mut system_idx, apply_moves)
+        .add_sequential_system(&mut system_idx, track_first_touched)
         // then, cleanup systems
+        .add_sequential_system(&mut system_idx, process_item_pickup)
+        .add_sequential_system(&mut system_idx, process_item_drop)
+        // effects of all kinds turn into damage and healing, which are resolved together
+        .add_sequential_system(&mut system_idx, process_item_use)
+        .add_sequential_system(&mut system_idx, process_equipment_events)
+        // whatever the player agreed to in conversation
+        .add_sequential_system(&mut system_idx, update_story_flags)
+        .add_sequential_system(&mut system_idx, process_item_gifts)
         .add_sequential_system(&mut system_idx, process_combat_event)
+        .add_sequential_system(&mut system_idx, apply_status_effects)
         .add_sequential_system(&mut system_idx, process_suffers_damage_event)
+        .add_sequential_system(&mut system_idx, process_healing_event)
+        // fights and conversations both change who likes the player, and that can start fights
+        .add_sequential_system(&mut system_idx, update_reputation)
+        .add_sequential_system(&mut system_idx, turn_hostile)
+        .add_sequential_system(&mut system_idx, check_player_death)
         .add_sequential_system(&mut system_idx, update_blocked_map)
         .add_sequential_system(&mut system_idx, update_combat_stats_map)
         .add_sequential_system(&mut system_idx, compute_viewsheds)
         .add_sequential_system(&mut system_idx, update_map_visibility)
-        .add_sequential_system(&mut system_idx, rebuild_visual_tiles)
+        .add_sequential_system(&mut system_idx, announce_landmarks)
+        // quests can finish on kills and sightings, so they go once those are all in
+        .add_sequential_system(&mut system_idx, update_quests)
+        .add_sequential_system(&mut system_idx, check_endings)
         .add_sequential_system(&mut system_idx, death_system)
+        .add_sequential_system(&mut system_idx, end_dialogue_on_death)
         .add_sequential_system(&mut system_idx, remove_dead_from_maps)
-        .add_sequential_system(&mut system_idx, update_logs)
+        .add_sequential_system(&mut system_idx, invalidate_dijkstra_maps)
+        .add_sequential_system(&mut system_idx, track_auto_move)
+        .add_sequential_system(&mut system_idx, record_logs)
         // finally, let the next entity take their turn
         .add_sequential_system(&mut system_idx, next_turn)
         .add_sequential_system(&mut system_idx, drain_turn_events);
@@ -58,6 +112,19 @@ pub fn world_tick(world: &mut World) {
     while start.elapsed().as_millis() < budget_ms {
         full_stage.run(world);
 
+        // swapping out the whole level is easier with the world to ourselves
+        let level_change = world
+            .get_resource_mut::<PendingLevelChange>()
+            .and_then(|mut pending| pending.0.take());
+        if let Some(direction) = level_change {
+            levels::change_level(world, direction);
+        }
+
+        // nothing more happens once the player is dead; the game over screen takes it from here
+        if world.contains_resource::<GameOverInfo>() {
+            break;
+        }
+
         // In the extremely common case where the player comes up twice, or they took no action,
         // we know nothing else is going to happen and we can stop immediately
         if world
@@ -123,39 +190,13 @@ pub fn clear_indexing_requests(mut commands: Commands, q: Query<Entity, With<Wan
     }
 }
 
-pub fn get_player_input(kb_input: Res<Input<KeyCode>>, mut input_state: ResMut<PlayerInputState>) {
-    *input_state = PlayerInputState::default();
-
-    if kb_input.any_just_pressed([KeyCode::A, KeyCode::Left, KeyCode::Numpad4]) {
-        input_state.left_pressed = true;
-    }
-
-    if kb_input.any_just_pressed([KeyCode::D, KeyCode::Right, KeyCode::Numpad6]) {
-        input_state.right_pressed = true;
-    }
-
-    if kb_input.any_just_pressed([KeyCode::W, KeyCode::Up, KeyCode::Numpad8]) {
-        input_state.up_pressed = true;
-    }
-
-    if kb_input.any_just_pressed([KeyCode::S, KeyCode::Down, KeyCode::Numpad2]) {
-        input_state.down_pressed = true;
-    }
-
-    if kb_input.just_pressed(KeyCode::Space) {
-        input_state.pass_pressed = true;
-    }
-
-    if input_state.up_pressed && input_state.down_pressed {
-        input_state.up_pressed = false;
-        input_state.down_pressed = false;
-    }
-
-    if input_state.left_pressed && input_state.right_pressed {
-        input_state.left_pressed = false;
-        input_state.right_pressed = false;
-    }
-}
+/// Everything about the player that matters when deciding what their input does
+type PlayerActionQuery<'a> = (
+    &'a WorldPos,
+    Option<&'a Inventory>,
+    Option<&'a Viewshed>,
+    Option<&'a StatusEffects>,
+);
 
 pub fn handle_input(
     // if this is set, we don't allow this system to go again, so a player can't move twice in one
@@ -167,12 +208,15 @@ pub fn handle_input(
     input: Res<PlayerInputState>,
     map: Res<Map>,
     turn_order: Res<TurnOrder>,
-    // TODO: do the actual move in a knock-on system, so everything is immutable except event launching
-    mut player_query: Query<(&mut WorldPos,), With<Player>>,
+    player_query: Query<PlayerActionQuery, With<Player>>,
+    item_query: Query<(Entity, &WorldPos), (With<Item>, Without<Player>)>,
+    talker_query: Query<(), With<Talker>>,
+    reactions: Reactions,
     blocked: Res<BlockedTiles>,
     combats: Res<CombatStatsTiles>,
-    // TODO: send this to a back system maybe?
-    mut player_map: ResMut<PlayerDistanceMap>,
+    mut level_change: ResMut<PendingLevelChange>,
+    mut targeting: ResMut<TargetingMode>,
+    mut rng: ResMut<GameRng>,
     mut events: ResMut<CallbackEvents>,
 ) {
     let entity = match turn_order.current_holder() {
@@ -180,7 +224,7 @@ pub fn handle_input(
         None => return,
     };
 
-    let (mut wp,) = match player_query.get_mut(entity) {
+    let (wp, inventory, viewshed, effects) = match player_query.get(entity) {
         Ok(tup) => tup,
         // not the player's turn, so do nothing
         Err(_) => return,
@@ -195,6 +239,20 @@ pub fn handle_input(
     // running more than once per frame
     player_lock.0 = true;
 
+    // while aiming, everything is about the cursor; the player only acts once it's confirmed
+    if targeting.0.is_some() {
+        targeting::handle_targeting_input(
+            entity,
+            *wp,
+            viewshed,
+            &*input,
+            &*map,
+            &mut *targeting,
+            &mut *events,
+        );
+        return;
+    }
+
     let mut new_wp = *wp;
     if input.left_pressed {
         new_wp.x -= 1;
@@ -209,35 +267,186 @@ pub fn handle_input(
         new_wp.y -= 1;
     }
 
+    // a confused player doesn't always end up going the way they meant to
+    if new_wp != *wp && status::staggers(effects, &mut rng.0) {
+        if let Some(stumble) = map.adjacent(*wp).choose(&mut rng.0) {
+            new_wp = stumble;
+        }
+    }
+
     if input.pass_pressed {
-        events.send(EntityFinishedTurn { entity });
+        events.send(EntityFinishedTurn {
+            entity,
+            cost: ActionCost::Wait,
+        });
+    } else if input.descend_pressed || input.ascend_pressed {
+        let (stairs, direction) = if input.descend_pressed {
+            (TileType::DownStairs, StairsDirection::Down)
+        } else {
+            (TileType::UpStairs, StairsDirection::Up)
+        };
+
+        if map.get_tile(*wp) == stairs {
+            events.send(EntityFinishedTurn {
+                entity,
+                cost: ActionCost::Stairs,
+            });
+            level_change.0 = Some(direction);
+        } else {
+            player_no_action.0 = true;
+        }
+    } else if input.pickup_pressed {
+        // if there's a pile, take the oldest thing first
+        let here = item_query
+            .iter()
+            .filter(|(_, item_wp)| **item_wp == *wp)
+            .map(|(item, _)| item)
+            .min_by_key(|item| item.id());
+
+        match here {
+            Some(item) if inventory.is_some() => {
+                events.send(EntityFinishedTurn {
+                    entity,
+                    cost: ActionCost::PickUp,
+                });
+                events.send(ItemPickedUp { entity, item });
+            }
+            _ => {
+                player_no_action.0 = true;
+                events.send(LogIssuedEvent {
+                    log: Log {
+                        message: "There is nothing here to pick up.".to_string(),
+                    },
+                });
+            }
+        }
+    } else if input.travel_pressed {
+        // picking the spot is free; the walking is what takes turns
+        player_no_action.0 = true;
+        targeting.0 = Some(TargetingCursor {
+            purpose: TargetPurpose::Travel,
+            pos: *wp,
+            range: i32::MAX,
+        });
+        events.send(LogIssuedEvent {
+            log: Log {
+                message: "Pick a destination with the movement keys; enter to travel, backspace to cancel."
+                    .to_string(),
+            },
+        });
+    } else if input.explore_pressed {
+        // the first step comes next frame, like every other one
+        player_no_action.0 = true;
+        events.send(PlayerStartsAutoMove {
+            kind: AutoMoveKind::Explore,
+        });
+    } else if let Some(idx) = input.drop_item {
+        match inventory.and_then(|inv| inv.items.get(idx)) {
+            Some(item) => {
+                events.send(EntityFinishedTurn {
+                    entity,
+                    cost: ActionCost::Drop,
+                });
+                events.send(ItemDropped {
+                    entity,
+                    item: *item,
+                });
+            }
+            None => {
+                player_no_action.0 = true;
+            }
+        }
+    } else if let Some(idx) = input.use_item {
+        // whether this takes a turn depends on whether it works, which is for the item to decide
+        match inventory.and_then(|inv| inv.items.get(idx)) {
+            Some(item) => {
+                events.send(EntityUsesItem {
+                    entity,
+                    item: *item,
+                    target: None,
+                });
+            }
+            None => {
+                player_no_action.0 = true;
+            }
+        }
+    } else if map.step_distance(*wp, new_wp) > 1 {
+        // a diagonal, with diagonal movement turned off
+        player_no_action.0 = true;
     } else if new_wp != *wp {
-        if let Some(defender) = combats.get_any(new_wp) {
-            events.send(EntityFinishedTurn { entity });
+        // nobody gets bumped into around a corner that can't be stepped around
+        let bumped = combats
+            .get_any(new_wp)
+            .filter(|_| map.is_adjacent(*wp, new_wp));
+        if let Some(speaker) = bumped.filter(|e| talker_query.get(*e).is_ok()) {
+            // talking is free; it's whatever gets agreed to that might cost something
+            player_no_action.0 = true;
+            events.send(PlayerStartsDialogue { speaker });
+        } else if let Some(friend) =
+            bumped.filter(|e| reactions.toward_player(*e) == Reaction::Friendly)
+        {
+            // friends let the player by, rather than getting hit
+            events.send(EntityFinishedTurn {
+                entity,
+                cost: ActionCost::Move,
+            });
+            events.send(EntityMovedEvent {
+                entity,
+                old_pos: *wp,
+                new_pos: new_wp,
+            });
+            events.send(EntityMovedEvent {
+                entity: friend,
+                old_pos: new_wp,
+                new_pos: *wp,
+            });
+        } else if let Some(defender) = bumped {
+            events.send(EntityFinishedTurn {
+                entity,
+                cost: ActionCost::Attack,
+            });
             events.send(EntityMeleeAttacks {
                 attacker: entity,
                 defender,
             });
-        } else if can_pass(new_wp, &*map, &*blocked) {
-            events.send(EntityFinishedTurn { entity });
+        } else if can_pass(*wp, new_wp, &*map, &*blocked) {
+            events.send(EntityFinishedTurn {
+                entity,
+                cost: ActionCost::Move,
+            });
             events.send(EntityMovedEvent {
                 entity,
                 old_pos: *wp,
                 new_pos: new_wp,
             });
-            *wp = new_wp;
-
-            // Note we don't want to block on "temporary" blocks, just walls; this has better
-            // monster behavior resulting (piling vs random running around)
-            // TODO: pull this out to a separate system that triggers on move
-            let new_player_map = dijkstra::distance_dijkstra_map(&*map, [new_wp].iter(), |_| false);
-            player_map.0 = new_player_map;
         }
     } else {
         player_no_action.0 = true;
     }
 }
 
+/// The map decides what's adjacent, so it needs to know whether diagonals are allowed; a new map
+/// (or a changed setting) gets told here. Everything pathing-related is out of date after that.
+fn apply_movement_setting(
+    settings: Res<GameSettings>,
+    mut map: ResMut<Map>,
+    mut dijkstra_maps: ResMut<DijkstraMaps>,
+) {
+    if map.diagonals() != settings.diagonal_movement {
+        map.set_diagonals(settings.diagonal_movement);
+        *dijkstra_maps = DijkstraMaps::default();
+    }
+}
+
+/// Moves are only ever asked for with events; this is where they actually happen
+fn apply_moves(mut q: Query<&mut WorldPos>, events: Res<CallbackEvents>) {
+    for event in events.iter::<EntityMovedEvent>() {
+        if let Ok(mut wp) = q.get_mut(event.entity) {
+            *wp = event.new_pos;
+        }
+    }
+}
+
 // TODO: make a macro to do this for every indexed component
 fn update_blocked_map(
     q: Query<(), (With<WorldPos>, With<BlocksMovement>)>,
@@ -273,9 +482,19 @@ fn update_combat_stats_map(
     }
 }
 
-fn next_turn(events: Res<CallbackEvents>, mut turns: ResMut<TurnOrder>) {
-    if events.is_nonempty::<EntityFinishedTurn>() {
-        turns.end_turn();
+/// Whoever just went has to wait for as long as their action took them
+fn next_turn(
+    events: Res<CallbackEvents>,
+    mut turns: ResMut<TurnOrder>,
+    speed_query: Query<(Option<&Speed>, Option<&StatusEffects>)>,
+) {
+    if let Some(event) = events.iter::<EntityFinishedTurn>().next() {
+        let speed = match speed_query.get(event.entity) {
+            Ok((speed, Some(effects))) => effects.speed(speed.copied().unwrap_or_default()),
+            Ok((speed, None)) => speed.copied().unwrap_or_default(),
+            Err(_) => Speed::default(),
+        };
+        turns.end_turn(event.entity, speed.time_for(event.cost));
     }
 }
 
@@ -283,13 +502,26 @@ fn drain_turn_events(mut events: ResMut<CallbackEvents>) {
     events.clear();
 }
 
-fn can_pass(new_wp: WorldPos, map: &Map, blocked: &BlockedTiles) -> bool {
-    map.passable(new_wp) && !blocked.0.has_any(new_wp)
+/// Whether a single step is allowed; adjacent tiles only, and no squeezing past corners
+fn can_pass(old_wp: WorldPos, new_wp: WorldPos, map: &Map, blocked: &BlockedTiles) -> bool {
+    map.adjacent(old_wp).any(|tile| tile == new_wp) && !blocked.0.has_any(new_wp)
 }
 
-pub fn death_system(events: ResMut<CallbackEvents>, mut commands: Commands) {
+pub fn death_system(
+    events: ResMut<CallbackEvents>,
+    mut commands: Commands,
+    inventory_query: Query<(&Inventory, &WorldPos)>,
+) {
     for event in events.iter::<EntityDies>() {
-        let EntityDies { entity } = *event;
+        let EntityDies { entity, .. } = *event;
+
+        // whatever they were carrying ends up on the floor
+        if let Ok((inventory, wp)) = inventory_query.get(entity) {
+            for item in inventory.items.iter() {
+                commands.entity(*item).insert(*wp).remove::<Equipped>();
+            }
+        }
+
         commands.entity(entity).despawn();
     }
 }
@@ -302,220 +534,13 @@ pub fn remove_dead_from_maps(
     mut cs_maps: ResMut<CombatStatsTiles>,
 ) {
     for event in events.iter::<EntityDies>() {
-        let EntityDies { entity } = *event;
+        let EntityDies { entity, .. } = *event;
         turns.remove_from_turn_order(entity);
         block_maps.remove_entity_anywhere(entity);
         cs_maps.remove_entity_anywhere(entity);
     }
 }
 
-pub fn aim_camera(
-    window: Res<WindowDescriptor>,
-    map: Res<Map>,
-    player_query: Query<(&Player, &WorldPos)>,
-    mut camera_query: Query<(&PlayerCamera, &mut Transform)>,
-) {
-    fn get_desired_wp_pt(
-        player_wp_pt: i32,
-        map_min_wp: i32,
-        map_max_wp: i32,
-        window_size_px: f32,
-    ) -> f32 {
-        let window_size_wp = window_size_px / TILE_SIZE;
-
-        // less than this and we have empty space on the left
-        let camera_min_wp = map_min_wp as f32 + (window_size_wp / 2.0) - 0.5;
-
-        // more than this and we have empty space on the right
-        let camera_max_wp = map_max_wp as f32 - (window_size_wp / 2.0) + 0.5;
-
-        // quick check: min_wp >= max_wp iff window_size >= map_width
-        // in which case just center the map
-        let desired_wp = player_wp_pt as f32;
-        if camera_min_wp >= camera_max_wp {
-            (map_min_wp + map_max_wp) as f32 / 2.0
-        } else if desired_wp < camera_min_wp {
-            camera_min_wp
-        } else if desired_wp > camera_max_wp {
-            camera_max_wp
-        } else {
-            desired_wp
-        }
-    }
-
-    // we need the player's position to center the camera
-    let player_wp: WorldPos = match player_query.get_single() {
-        Ok(player) => *player.1,
-        // if no player, just end the system
-        Err(_) => {
-            return;
-        }
-    };
-
-    let bounds = map.bounding_box();
-
-    let desired_x_wp = get_desired_wp_pt(player_wp.x, bounds.x_min, bounds.x_max, window.width);
-    let desired_y_wp = get_desired_wp_pt(player_wp.y, bounds.y_min, bounds.y_max, window.height);
-
-    // aim the camera at the player
-    for (_, mut transform) in camera_query.iter_mut() {
-        let x_dist = desired_x_wp * TILE_SIZE - transform.translation.x;
-        let y_dist = desired_y_wp * TILE_SIZE - transform.translation.y;
-
-        transform.translation.x += x_dist;
-        transform.translation.y += y_dist;
-    }
-}
-
-pub fn world_pos_to_visual_system(mut wp_query: Query<(&WorldPos, &mut Transform)>) {
-    // lock everything to their world position (that is, graphical transform is derived from WP)
-    for (wp, mut transform) in wp_query.iter_mut() {
-        let wp: WorldPos = *wp;
-        transform.translation.x = wp.x as f32 * TILE_SIZE;
-        transform.translation.y = wp.y as f32 * TILE_SIZE;
-    }
-}
-
-/// Make the visible (bevy rendering) component reflect the actual viewing state, if relevant
-pub fn hide_unseen_things(
-    player_query: Query<&Viewshed, (With<Player>,)>,
-    mut to_hide_query: Query<(&WorldPos, &mut Visibility), (With<RequiresSeen>,)>,
-) {
-    let player_vs = match player_query.iter().next() {
-        Some(vs) => vs.visible_tiles.clone(),
-        None => HashSet::new(),
-    };
-
-    for (wp, mut visible) in to_hide_query.iter_mut() {
-        if player_vs.contains(&*wp) {
-            visible.is_visible = true;
-        } else {
-            visible.is_visible = false;
-        }
-    }
-}
-
-pub fn rebuild_visual_tiles(
-    mut commands: Commands,
-    events: Res<CallbackEvents>,
-    q: Query<(Entity, &VisualTile)>,
-    map: Res<Map>,
-    sheet: Res<BasicTilesAtlas>,
-) {
-    // even if we send a zillion, we only rebuild once
-    if events.is_nonempty::<MapChangedEvent>() {
-        return;
-    }
-
-    // wipe out anything previous existing, if any
-    for (e, _) in q.iter() {
-        commands.entity(e).despawn();
-    }
-
-    // then build new tiles
-    for tile_data in map.tiles() {
-        let tile_idx = match tile_data.tile_type {
-            TileType::Wall => 8 * 16 + 3,
-            TileType::Floor => 7 * 16 + 8,
-        };
-
-        if tile_data.seen {
-            let color = if tile_data.visible {
-                match tile_data.tile_type {
-                    TileType::Floor => Color::rgb(0.4, 0.75, 0.4),
-                    TileType::Wall => Color::rgb(0.8, 0.79, 0.57),
-                }
-            } else {
-                Color::GRAY
-            };
-            commands
-                .spawn()
-                .insert(VisualTile(tile_data.tile_type))
-                .insert_bundle(make_basic_sprite_bundle(tile_idx, &sheet.0, color))
-                .insert(tile_data.world_pos)
-                // TODO: tile layers?
-                .insert(Transform::default());
-        }
-    }
-}
-
-pub fn monster_ai(
-    mut query_set: QuerySet<(
-        QueryState<(Entity, &WorldPos), With<Player>>,
-        // TODO: do the actual move in a knock-on system, so everything is immutable except event launching
-        QueryState<(&Viewshed, &mut WorldPos), With<MonsterAI>>,
-    )>,
-    map: Res<Map>,
-    blocked: Res<BlockedTiles>,
-    player_map: Res<PlayerDistanceMap>,
-    turns: Res<TurnOrder>,
-    mut events: ResMut<CallbackEvents>,
-) {
-    let entity = match turns.current_holder() {
-        Some(entity) => entity,
-        None => return,
-    };
-
-    // if monsters can't choose an action the game still moves on, so we'll just preemptively end
-    // the turn so we don't forget to do it at the end
-    if query_set.q1().get(entity).is_ok() {
-        events.send(EntityFinishedTurn { entity });
-    } else {
-        return;
-    }
-
-    let (player_entity, player_pos) = match query_set.q0().iter().next() {
-        Some((entity, wp)) => (entity, *wp),
-        // no player no action
-        None => return,
-    };
-
-    query_set
-        .q0()
-        .get(player_entity)
-        .expect("Thing should exist");
-
-    match query_set.q1().get_mut(entity) {
-        Ok((vs, mut wp)) => {
-            if !vs.visible_tiles.contains(&player_pos) {
-                // player is not visible; do nothing
-                // don't chase them all over the map, only if they can be seen
-                return;
-            }
-
-            if wp.dist(player_pos) <= 1 {
-                events.send(EntityMeleeAttacks {
-                    attacker: entity,
-                    defender: player_entity,
-                });
-            } else {
-                let curr_dist = player_map.0.get(&*wp).copied().unwrap_or(i32::MAX);
-
-                let mut new_wp = *wp;
-                let mut best_dest = curr_dist;
-
-                for adj_tile in map.adjacent(*wp).filter(|w| !blocked.has_any(*w)) {
-                    let adj_dist = player_map.0.get(&adj_tile).copied().unwrap_or(i32::MAX);
-                    if adj_dist < best_dest {
-                        best_dest = adj_dist;
-                        new_wp = adj_tile;
-                    }
-                }
-
-                if new_wp != *wp {
-                    events.send(EntityMovedEvent {
-                        entity,
-                        old_pos: *wp,
-                        new_pos: new_wp,
-                    });
-                    *wp = new_wp;
-                }
-            }
-        }
-        Err(e) => unreachable!("We know the entity matches the query; error was {:?}", e),
-    }
-}
-
 pub fn process_suffers_damage_event(
     mut cs_query: Query<&mut CombatStats>,
     name_query: Query<&EntityName>,
@@ -524,7 +549,11 @@ pub fn process_suffers_damage_event(
     let mut deaths = Vec::new();
     let mut logs = Vec::new();
     for event in events.iter::<EntitySuffersDamage>() {
-        let EntitySuffersDamage { entity, damage } = *event;
+        let EntitySuffersDamage {
+            entity,
+            damage,
+            source,
+        } = *event;
 
         match cs_query.get_mut(entity) {
             Ok(mut cs) => {
@@ -540,7 +569,10 @@ pub fn process_suffers_damage_event(
                             message: format!("{} has died!", name),
                         },
                     });
-                    deaths.push(EntityDies { entity });
+                    deaths.push(EntityDies {
+                        entity,
+                        killer: source,
+                    });
                 } else {
                     logs.push(LogIssuedEvent {
                         log: Log {
@@ -560,22 +592,111 @@ pub fn process_suffers_damage_event(
     }
 }
 
+pub fn process_healing_event(
+    mut cs_query: Query<&mut CombatStats>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut logs = Vec::new();
+    for event in events.iter::<EntityHealed>() {
+        let EntityHealed { entity, amount } = *event;
+
+        if let Ok(mut cs) = cs_query.get_mut(entity) {
+            // the dead stay dead
+            if cs.hp <= 0 {
+                continue;
+            }
+            cs.hp = (cs.hp + amount).min(cs.max_hp);
+
+            let name = name_query
+                .get(entity)
+                .map(|n| n.0.as_str())
+                .unwrap_or("[unknown]");
+            logs.push(LogIssuedEvent {
+                log: Log {
+                    message: format!("{} is healed, and has {} health.", name, cs.hp),
+                },
+            });
+        }
+    }
+    for log in logs {
+        events.send(log);
+    }
+}
+
+/// Everything needed to end the game and write down how it happened for the game over screen
+#[derive(SystemParam)]
+pub struct GameOverTrigger<'w, 's> {
+    commands: Commands<'w, 's>,
+    turn: Res<'w, CurrentTurnNumber>,
+    depth: Res<'w, CurrentDepth>,
+    state: ResMut<'w, State<GameState>>,
+}
+
+impl<'w, 's> GameOverTrigger<'w, 's> {
+    /// `ending` is the name of the ending reached, if it was one; otherwise the player died
+    pub fn end_game(&mut self, cause: String, ending: Option<String>) {
+        self.commands.insert_resource(GameOverInfo {
+            turn: self.turn.0,
+            depth: self.depth.0,
+            cause,
+            ending,
+        });
+        // the only way this fails is if we're already headed there
+        let _ = self.state.set(GameState::GameOver);
+    }
+}
+
+/// When the player dies, the game is over
+pub fn check_player_death(
+    player_query: Query<(), With<Player>>,
+    name_query: Query<&EntityName>,
+    effects_query: Query<&StatusEffects>,
+    mut game_over: GameOverTrigger,
+    events: Res<CallbackEvents>,
+) {
+    for event in events.iter::<EntityDies>() {
+        let EntityDies { entity, killer } = *event;
+        if player_query.get(entity).is_err() {
+            continue;
+        }
+
+        // nobody did it, so it was probably something lingering
+        let lingering = |kind: StatusKind| {
+            effects_query
+                .get(entity)
+                .map_or(false, |effects| effects.0.iter().any(|e| e.kind == kind))
+        };
+        let cause = match killer.and_then(|k| name_query.get(k).ok()) {
+            Some(name) => format!("Killed by {}", name.0),
+            None if lingering(StatusKind::Poisoned) => "Succumbed to poison".to_string(),
+            None if lingering(StatusKind::Bleeding) => "Bled to death".to_string(),
+            None => "Died of unknown causes".to_string(),
+        };
+        game_over.end_game(cause, None);
+    }
+}
+
 pub fn process_combat_event(
     mut events: ResMut<CallbackEvents>,
-    cs_query: Query<&CombatStats>,
+    cs_query: Query<(&CombatStats, Option<&Inventory>)>,
+    equipment_query: Query<&Equippable, With<Equipped>>,
+    on_hit_query: Query<&InflictsOnHit>,
     name_query: Query<&EntityName>,
 ) {
     let mut damage: Vec<EntitySuffersDamage> = Vec::new();
+    let mut afflictions: Vec<EntityGainsStatus> = Vec::new();
     let mut logs: Vec<LogIssuedEvent> = Vec::new();
 
     for event in events.iter::<EntityMeleeAttacks>() {
         let EntityMeleeAttacks { attacker, defender } = *event;
+        // gear counts, so this is never just the CombatStats component
         let attacker_cs: CombatStats = match cs_query.get(attacker) {
-            Ok(cs) => *cs,
+            Ok((cs, inv)) => effective_stats(*cs, inv, &equipment_query),
             Err(_) => continue,
         };
         let defender_cs: CombatStats = match cs_query.get(defender) {
-            Ok(cs) => *cs,
+            Ok((cs, inv)) => effective_stats(*cs, inv, &equipment_query),
             Err(_) => continue,
         };
 
@@ -601,71 +722,40 @@ pub fn process_combat_event(
         damage.push(EntitySuffersDamage {
             entity: defender,
             damage: inflicted,
+            source: Some(attacker),
         });
+
+        // a hit that doesn't get through doesn't leave anything behind either
+        if let (true, Ok(on_hit)) = (inflicted > 0, on_hit_query.get(attacker)) {
+            afflictions.extend(on_hit.0.iter().map(|effect| EntityGainsStatus {
+                entity: defender,
+                effect: *effect,
+                source: Some(attacker),
+            }));
+        }
     }
 
     for damage in damage {
         events.send(damage);
     }
+    for affliction in afflictions {
+        events.send(affliction);
+    }
     for log in logs {
         events.send(log);
     }
 }
 
-pub fn update_fps_text(
-    diagnostics: Res<Diagnostics>,
-    kb_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Text, Option<&mut Visibility>), With<FpsTextBox>>,
-) {
-    let toggled: bool = kb_input.just_pressed(KeyCode::F);
-    for (mut text, vis) in query.iter_mut() {
-        if toggled {
-            vis.map(|mut v| v.is_visible = !v.is_visible);
-        }
-        if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
-            if let Some(average) = fps.average() {
-                text.sections[1].value = format!("{:.2}", average);
-            }
-        }
-    }
-}
-
-pub fn update_logs(
+pub fn record_logs(
     turn: Res<CurrentTurnNumber>,
-    asset_server: Res<AssetServer>,
     mut events: ResMut<CallbackEvents>,
     mut logs: ResMut<Logs>,
-    mut text_component_query: Query<&mut Text, With<LogsTextBox>>,
 ) {
-    let mut changed = false;
-
     for event in events.drain::<LogIssuedEvent>() {
         logs.push(LogInfo {
             log: event.log,
             issue_round: turn.0,
         });
-        changed = true;
-    }
-
-    // no reason rebuilding the component with no changes
-    if !changed {
-        return;
-    }
-
-    let style = TextStyle {
-        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-        font_size: 20.0,
-        color: Color::WHITE,
-    };
-
-    for mut text in text_component_query.iter_mut() {
-        text.sections = logs
-            .iter((0..5).rev())
-            .map(|log| TextSection {
-                value: format!("[{}]: {}\n", log.issue_round, log.log.message),
-                style: style.clone(),
-            })
-            .collect();
     }
 }
 
@@ -684,6 +774,7 @@ pub fn handle_end_of_turn(
         counter.0 += 1;
         events.send(EntityFinishedTurn {
             entity: next_entity,
+            cost: ActionCost::Wait,
         });
     }
 }
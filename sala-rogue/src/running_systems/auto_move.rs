@@ -0,0 +1,213 @@
+//! The player doing something over many turns on their own, like walking across the level or
+//! exploring it.
+//! Whatever is doing it only ever presses keys the player could have pressed themselves, so the
+//! turns play out just like any other.
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use super::{describe_item, dijkstra, pathfinding, Reactions};
+use crate::components::*;
+use crate::map::Map;
+use crate::resources::*;
+
+/// Stands in for the keyboard while the player is moving on their own. Runs after the real input
+/// is read; any real key press stops the whole thing instead.
+pub fn auto_move_input(
+    mut auto_move: ResMut<AutoMove>,
+    mut input: ResMut<PlayerInputState>,
+    map: Res<Map>,
+    blocked: Res<BlockedTiles>,
+    player_query: Query<&WorldPos, With<Player>>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let current = match auto_move.current {
+        Some(current) => current,
+        None => return,
+    };
+
+    if *input != PlayerInputState::default() {
+        auto_move.current = None;
+        return;
+    }
+
+    let player_pos = match player_query.get_single() {
+        Ok(wp) => *wp,
+        Err(_) => {
+            auto_move.current = None;
+            return;
+        }
+    };
+
+    let next_step = match current {
+        AutoMoveKind::Travel { destination } => {
+            if player_pos == destination {
+                auto_move.current = None;
+                return;
+            }
+            pathfinding::find_path(&*map, &*blocked, player_pos, destination)
+                .and_then(|path| path.first().copied())
+                .ok_or("You can't find a way there.")
+        }
+        AutoMoveKind::Explore => {
+            auto_move.explored_from.insert(player_pos);
+            let frontier = explore_frontier(&*map, &auto_move.explored_from);
+            if frontier.is_empty() {
+                Err("There's nothing left to explore here.")
+            } else {
+                let distances = dijkstra::distance_dijkstra_map(&*map, frontier.iter(), |_| false);
+                let dist = |wp: &WorldPos| distances.get(wp).copied().unwrap_or(i32::MAX);
+                map.adjacent(player_pos)
+                    .filter(|wp| !blocked.has_any(*wp))
+                    .filter(|wp| dist(wp) < dist(&player_pos))
+                    .min_by_key(|wp| dist(wp))
+                    .ok_or("You can't get anywhere you haven't seen.")
+            }
+        }
+    };
+
+    match next_step {
+        Ok(step) => press_toward(&mut *input, player_pos, step),
+        Err(reason) => {
+            auto_move.current = None;
+            events.send(LogIssuedEvent {
+                log: Log {
+                    message: reason.to_string(),
+                },
+            });
+        }
+    }
+}
+
+/// Seen tiles the player could stand on, right next to tiles they've never seen. Places the
+/// player has already explored from don't count; if standing there didn't reveal anything,
+/// going back won't either.
+fn explore_frontier(map: &Map, explored_from: &HashSet<WorldPos>) -> Vec<WorldPos> {
+    let unseen_neighbor = |wp: WorldPos| {
+        [(0, 1), (0, -1), (1, 0), (-1, 0)].iter().any(|(dx, dy)| {
+            !map.is_seen(WorldPos {
+                x: wp.x + dx,
+                y: wp.y + dy,
+            })
+        })
+    };
+
+    map.tiles()
+        .filter(|tile| tile.seen && !tile.tile_type.blocks_movement())
+        .map(|tile| tile.world_pos)
+        .filter(|wp| !explored_from.contains(wp))
+        .filter(|wp| unseen_neighbor(*wp))
+        .collect()
+}
+
+/// Fill in the movement keys to step from one tile to the one next to it
+fn press_toward(input: &mut PlayerInputState, from: WorldPos, to: WorldPos) {
+    input.left_pressed = to.x < from.x;
+    input.right_pressed = to.x > from.x;
+    input.up_pressed = to.y > from.y;
+    input.down_pressed = to.y < from.y;
+}
+
+/// Starts whatever the player asked for, and stops it once anything worth a look happens: an
+/// enemy coming into view, or anything making it into the log that has to do with the player or
+/// what they can see. Exploring also stops for every new item spotted, and won't start at all
+/// with enemies around.
+pub fn track_auto_move(
+    player_query: Query<(Entity, &Viewshed), With<Player>>,
+    creature_query: Query<(Entity, &WorldPos, Option<&EntityName>), With<CombatStats>>,
+    reactions: Reactions,
+    item_query: Query<(Entity, &WorldPos, &Item, Option<&EntityName>)>,
+    turns: Res<TurnOrder>,
+    mut auto_move: ResMut<AutoMove>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let empty = HashSet::new();
+    let (player, visible_tiles) = match player_query.get_single() {
+        Ok((player, vs)) => (Some(player), &vs.visible_tiles),
+        Err(_) => (None, &empty),
+    };
+    let visible_monsters: HashSet<Entity> = creature_query
+        .iter()
+        .filter(|(entity, wp, _)| {
+            Some(*entity) != player
+                && visible_tiles.contains(wp)
+                && reactions.toward_player(*entity) == Reaction::Hostile
+        })
+        .map(|(entity, _, _)| entity)
+        .collect();
+    let visible_items: HashSet<Entity> = item_query
+        .iter()
+        .filter(|(_, wp, _, _)| visible_tiles.contains(wp))
+        .map(|(entity, _, _, _)| entity)
+        .collect();
+
+    let mut logs = Vec::new();
+    let mut just_started = false;
+    for event in events.iter::<PlayerStartsAutoMove>() {
+        match event.kind {
+            AutoMoveKind::Explore if !visible_monsters.is_empty() => {
+                logs.push("Not with enemies in view.".to_string());
+            }
+            AutoMoveKind::Explore => {
+                auto_move.current = Some(AutoMoveKind::Explore);
+                auto_move.explored_from.clear();
+                auto_move.noticed_items = visible_items.clone();
+                just_started = true;
+            }
+            kind => {
+                auto_move.current = Some(kind);
+                just_started = true;
+            }
+        }
+    }
+
+    if auto_move.current.is_some() && !just_started {
+        let new_monster = visible_monsters
+            .iter()
+            .find(|entity| !auto_move.visible_monsters.contains(entity));
+        let new_item = visible_items
+            .iter()
+            .find(|entity| !auto_move.noticed_items.contains(entity));
+
+        // whatever goes on out of sight (lingering effects ticking away, fights between monsters)
+        // isn't the player's business
+        let in_view = |entity: Entity| {
+            Some(entity) == player
+                || creature_query
+                    .get(entity)
+                    .map_or(false, |(_, wp, _)| visible_tiles.contains(wp))
+        };
+        let noticeable = turns.current_holder().map_or(false, in_view)
+            || events
+                .iter::<EntitySuffersDamage>()
+                .any(|e| in_view(e.entity))
+            || events.iter::<EntityHealed>().any(|e| in_view(e.entity))
+            || events
+                .iter::<EntityStatusChanged>()
+                .any(|e| in_view(e.entity))
+            || events.iter::<EntityDies>().any(|e| in_view(e.entity));
+
+        if let Some((_, _, name)) = new_monster.and_then(|e| creature_query.get(*e).ok()) {
+            auto_move.current = None;
+            let name = name.map(|n| n.0.as_str()).unwrap_or("Something");
+            logs.push(format!("{} comes into view.", name));
+        } else if noticeable && events.is_nonempty::<LogIssuedEvent>() {
+            auto_move.current = None;
+        } else if auto_move.current == Some(AutoMoveKind::Explore) {
+            if let Some((_, _, item, name)) = new_item.and_then(|e| item_query.get(*e).ok()) {
+                auto_move.current = None;
+                logs.push(format!("You spot {}.", describe_item(name, item)));
+            }
+        }
+    }
+
+    auto_move.visible_monsters = visible_monsters;
+    auto_move.noticed_items.extend(visible_items);
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
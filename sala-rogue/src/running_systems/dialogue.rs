@@ -0,0 +1,174 @@
+//! Talking to people. A conversation holds up the game until it's over: while it's going, the
+//! player's input picks choices instead of moving them around. Whatever the choices actually do
+//! goes out as events, for the usual systems to take care of.
+
+use bevy::prelude::*;
+
+use super::story::{StoryContext, StoryState};
+use crate::components::*;
+use crate::raws::{DialogueChoice, DialogueNode, DialogueRegistry};
+use crate::resources::*;
+
+/// The choices the player actually gets offered, in order; these are what get numbered
+pub fn available_choices<'n>(
+    context: &StoryContext,
+    node: &'n DialogueNode,
+) -> Vec<&'n DialogueChoice> {
+    node.choices
+        .iter()
+        .filter(|choice| context.all_hold(&choice.conditions))
+        .collect()
+}
+
+/// Bumping into someone who talks starts their conversation from the top
+pub fn start_dialogue(
+    mut events: ResMut<CallbackEvents>,
+    talker_query: Query<(&Talker, Option<&EntityName>)>,
+    registry: Res<DialogueRegistry>,
+    mut active: ResMut<ActiveDialogue>,
+) {
+    let mut logs = Vec::new();
+
+    for event in events.iter::<PlayerStartsDialogue>() {
+        let (talker, name) = match talker_query.get(event.speaker) {
+            Ok(tup) => tup,
+            Err(_) => continue,
+        };
+
+        match registry.get(&talker.dialogue) {
+            Some(def) => {
+                active.0 = Some(DialogueState {
+                    speaker: event.speaker,
+                    dialogue: def.name.clone(),
+                    node: def.start.clone(),
+                });
+            }
+            None => {
+                let name = name.map(|n| n.0.as_str()).unwrap_or("[unknown]");
+                logs.push(format!("{} has nothing to say.", name));
+            }
+        }
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
+
+/// Stands in for `handle_input` while a conversation is going: a number picks that choice, and
+/// cancel walks away. Either way, the player doesn't get to do anything else this frame.
+pub fn handle_dialogue_input(
+    mut player_lock: ResMut<PlayerMovedInFrame>,
+    mut player_no_action: ResMut<PlayerNoAction>,
+    input: Res<PlayerInputState>,
+    registry: Res<DialogueRegistry>,
+    story: StoryState,
+    mut active: ResMut<ActiveDialogue>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let state = match active.0.as_ref() {
+        Some(state) => state.clone(),
+        None => return,
+    };
+
+    // nothing happens mid-conversation, and nothing more happens once one is over
+    let already_locked = player_lock.0;
+    player_lock.0 = true;
+    player_no_action.0 = true;
+    if already_locked {
+        return;
+    }
+
+    let player = match story.player() {
+        Some(player) => player,
+        None => {
+            active.0 = None;
+            return;
+        }
+    };
+
+    if input.cancel_pressed {
+        active.0 = None;
+        return;
+    }
+
+    let node = match registry.node(&state) {
+        Some(node) => node,
+        None => {
+            active.0 = None;
+            return;
+        }
+    };
+
+    let choice = match story
+        .context()
+        .zip(input.use_item)
+        .and_then(|(context, idx)| available_choices(&context, node).get(idx).copied())
+    {
+        Some(choice) => choice,
+        None => return,
+    };
+
+    for outcome in choice.outcomes.iter() {
+        send_outcome(&mut *events, player, state.speaker, outcome.clone());
+    }
+
+    active.0 = choice.next.as_ref().map(|next| DialogueState {
+        node: next.clone(),
+        ..state
+    });
+}
+
+fn send_outcome(
+    events: &mut CallbackEvents,
+    player: Entity,
+    speaker: Entity,
+    outcome: DialogueOutcome,
+) {
+    match outcome {
+        DialogueOutcome::Say(message) => events.send(LogIssuedEvent {
+            log: Log { message },
+        }),
+        DialogueOutcome::SetFlag(flag) => events.send(StoryFlagChanged { flag, set: true }),
+        DialogueOutcome::ClearFlag(flag) => events.send(StoryFlagChanged { flag, set: false }),
+        DialogueOutcome::AddToCounter { counter, amount } => {
+            events.send(StoryCounterChanged { counter, amount })
+        }
+        DialogueOutcome::GiveItem { name, count } => events.send(EntityGivenItem {
+            entity: player,
+            name,
+            count,
+        }),
+        DialogueOutcome::TakeItem { name, count } => events.send(EntityLosesItem {
+            entity: player,
+            name,
+            count,
+        }),
+        DialogueOutcome::Heal(amount) => events.send(EntityHealed {
+            entity: player,
+            amount,
+        }),
+        DialogueOutcome::ChangeRegard(amount) => events.send(EntityRegardChanged {
+            entity: speaker,
+            amount,
+        }),
+        DialogueOutcome::ChangeStanding { faction, amount } => {
+            events.send(FactionStandingChanged { faction, amount })
+        }
+        DialogueOutcome::StartFight => events.send(EntityTurnsHostile { entity: speaker }),
+    }
+}
+
+/// Nobody talks to a corpse
+pub fn end_dialogue_on_death(events: Res<CallbackEvents>, mut active: ResMut<ActiveDialogue>) {
+    let speaker = match active.0.as_ref() {
+        Some(state) => state.speaker,
+        None => return,
+    };
+
+    if events.iter::<EntityDies>().any(|e| e.entity == speaker) {
+        active.0 = None;
+    }
+}
@@ -1,9 +1,12 @@
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BinaryHeap;
 use std::collections::HashMap;
 
+use bevy::prelude::*;
+
 use crate::components::*;
 use crate::map::*;
+use crate::resources::*;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
 struct TilePriority(i32, WorldPos);
@@ -31,23 +34,33 @@ pub fn distance_dijkstra_map<
     map: &Map,
     destinations: T,
     is_blocked: IsBlocked,
-) -> HashMap<WorldPos, i32> {
+) -> DijkstraMap {
+    seeded_dijkstra_map(map, destinations.map(|wp| (*wp, 0)), is_blocked)
+}
+
+/// Like `distance_dijkstra_map`, but every starting tile gets its own starting value; each tile
+/// ends up with the lowest of (start value + steps) over all the starting tiles.
+pub fn seeded_dijkstra_map<T: Iterator<Item = (WorldPos, i32)>, IsBlocked: Fn(WorldPos) -> bool>(
+    map: &Map,
+    seeds: T,
+    is_blocked: IsBlocked,
+) -> DijkstraMap {
     let start_time = std::time::Instant::now();
 
-    let mut distances = HashMap::new(); // map WorldPos -> distance to player
-    let mut to_process = BinaryHeap::new(); // newly adjacent tiles to consider
+    let mut distances = HashMap::new(); // map WorldPos -> distance to the nearest seed
+    let mut to_process = BinaryHeap::new(); // newly adjacent tiles to consider, closest first
 
-    for wp in destinations {
-        to_process.push(TilePriority(0, *wp));
+    for (wp, value) in seeds {
+        to_process.push(Reverse(TilePriority(value, wp)));
     }
 
-    while let Some(TilePriority(priority, wp)) = to_process.pop() {
+    while let Some(Reverse(TilePriority(priority, wp))) = to_process.pop() {
         let existing_priority = distances.get(&wp).copied().unwrap_or(i32::MAX);
         if priority < existing_priority {
             distances.insert(wp, priority);
             for tile in map.adjacent(wp) {
                 if !is_blocked(tile) {
-                    to_process.push(TilePriority(priority + 1, tile));
+                    to_process.push(Reverse(TilePriority(priority + 1, tile)));
                 }
             }
         }
@@ -58,3 +71,112 @@ pub fn distance_dijkstra_map<
 
     distances
 }
+
+/// Like `distance_dijkstra_map` from every member of a group at once, but each tile remembers
+/// the nearest two members (and which they are) rather than just the nearest distance
+pub fn nearest_two_map<T: Iterator<Item = (Entity, WorldPos)>>(
+    map: &Map,
+    members: T,
+) -> NearestTwoMap {
+    let members: Vec<(Entity, WorldPos)> = members.collect();
+
+    let mut found: NearestTwoMap = HashMap::new();
+    // (distance, tile, index into members), closest first
+    let mut to_process = BinaryHeap::new();
+    for (idx, (_, wp)) in members.iter().enumerate() {
+        to_process.push(Reverse((0, wp.x, wp.y, idx)));
+    }
+
+    while let Some(Reverse((dist, x, y, idx))) = to_process.pop() {
+        let wp = WorldPos { x, y };
+        let member = members[idx].0;
+        let nearest = found.entry(wp).or_insert_with(Vec::new);
+        if nearest.len() >= 2 || nearest.iter().any(|(_, m)| *m == member) {
+            continue;
+        }
+        nearest.push((dist, member));
+
+        for tile in map.adjacent(wp) {
+            to_process.push(Reverse((dist + 1, tile.x, tile.y, idx)));
+        }
+    }
+
+    found
+}
+
+/// How much running away is preferred to just keeping distance; above 1 means it's worth going
+/// past the player to get somewhere more open
+const FLEE_FACTOR: f32 = -1.2;
+
+/// The classic flee map: scale the distances by a negative number and let them settle again.
+/// Going downhill on the result leads away from the goal, but around it instead of into the
+/// nearest corner.
+pub fn flee_map(map: &Map, toward: &DijkstraMap) -> DijkstraMap {
+    let seeds = toward
+        .iter()
+        .map(|(wp, dist)| (*wp, (*dist as f32 * FLEE_FACTOR).round() as i32));
+    seeded_dijkstra_map(map, seeds, |_| false)
+}
+
+/// Anything that might change where a goal is marks its map for rebuilding
+pub fn invalidate_dijkstra_maps(
+    player_query: Query<(), With<Player>>,
+    monster_query: Query<(), With<MonsterAI>>,
+    mut maps: ResMut<DijkstraMaps>,
+    events: Res<CallbackEvents>,
+) {
+    for event in events.iter::<EntityMovedEvent>() {
+        if player_query.get(event.entity).is_ok() {
+            maps.mark_dirty(DijkstraGoal::TowardPlayer);
+            maps.mark_dirty(DijkstraGoal::FleePlayer);
+        }
+        if monster_query.get(event.entity).is_ok() {
+            maps.mark_dirty(DijkstraGoal::TowardAllies);
+        }
+    }
+
+    if events.is_nonempty::<ItemPickedUp>() || events.is_nonempty::<ItemDropped>() {
+        maps.mark_dirty(DijkstraGoal::TowardItems);
+    }
+
+    // whatever the dead were carrying may be on the floor now, and they're nobody's ally any more
+    if events.is_nonempty::<EntityDies>() {
+        maps.mark_dirty(DijkstraGoal::TowardItems);
+        maps.mark_dirty(DijkstraGoal::TowardAllies);
+    }
+}
+
+/// Rebuild whichever shared maps are out of date. This runs before anyone gets to act, so the
+/// moves and pickups of the last action have all landed.
+pub fn update_dijkstra_maps(
+    player_query: Query<&WorldPos, With<Player>>,
+    item_query: Query<&WorldPos, With<Item>>,
+    map: Res<Map>,
+    mut maps: ResMut<DijkstraMaps>,
+) {
+    let mut dirty = maps.take_dirty();
+    // the flee map is built from the toward map, so that one has to go first
+    dirty.sort_by_key(|goal| *goal == DijkstraGoal::FleePlayer);
+
+    // Note we don't want to block on "temporary" blocks, just walls; this has better
+    // monster behavior resulting (piling vs random running around)
+    for goal in dirty {
+        let built = match goal {
+            DijkstraGoal::TowardPlayer => {
+                distance_dijkstra_map(&*map, player_query.iter(), |_| false)
+            }
+            DijkstraGoal::FleePlayer => {
+                let toward = maps
+                    .get(DijkstraGoal::TowardPlayer)
+                    .cloned()
+                    .unwrap_or_default();
+                flee_map(&*map, &toward)
+            }
+            // only items lying around have a position
+            DijkstraGoal::TowardItems => distance_dijkstra_map(&*map, item_query.iter(), |_| false),
+            // built per faction when someone needs one, never ahead of time
+            DijkstraGoal::TowardAllies => continue,
+        };
+        maps.set(goal, built);
+    }
+}
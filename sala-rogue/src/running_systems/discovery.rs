@@ -0,0 +1,77 @@
+//! Noticing things for the first time. Seeing is worked out along with the rest of the player's
+//! visibility (see `update_map_visibility`); touching is worked out here, from whatever the player
+//! bumped into or stepped on this turn. Either way it happens once per entity, and other systems
+//! can react to `EntityFirstSeen` and `EntityFirstTouched` however they like.
+
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::resources::*;
+
+/// Things the player hasn't touched yet; once they have, they're marked `Touched`
+type UntouchedFilter = (With<RequiresSeen>, Without<Touched>);
+
+/// Bumping into something (to fight it or talk to it) or stepping onto it counts as touching it
+pub fn track_first_touched(
+    mut commands: Commands,
+    mut events: ResMut<CallbackEvents>,
+    player_query: Query<Entity, With<Player>>,
+    untouched_query: Query<(Entity, &WorldPos), UntouchedFilter>,
+) {
+    let player = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+
+    let mut touched: Vec<Entity> = Vec::new();
+
+    let bumped = events
+        .iter::<EntityMeleeAttacks>()
+        .filter(|e| e.attacker == player)
+        .map(|e| e.defender)
+        .chain(events.iter::<PlayerStartsDialogue>().map(|e| e.speaker));
+    touched.extend(bumped.filter(|e| untouched_query.get(*e).is_ok()));
+
+    for event in events.iter::<EntityMovedEvent>() {
+        if event.entity != player {
+            continue;
+        }
+        touched.extend(
+            untouched_query
+                .iter()
+                .filter(|(entity, wp)| **wp == event.new_pos && *entity != player)
+                .map(|(entity, _)| entity),
+        );
+    }
+
+    touched.sort_by_key(|e| e.id());
+    touched.dedup();
+
+    for entity in touched {
+        commands.entity(entity).insert(Touched);
+        events.send(EntityFirstTouched { entity });
+    }
+}
+
+/// Landmarks describe themselves the first time they come into view
+pub fn announce_landmarks(
+    mut events: ResMut<CallbackEvents>,
+    landmark_query: Query<(&Landmark, Option<&EntityName>)>,
+) {
+    let mut logs = Vec::new();
+
+    for event in events.iter::<EntityFirstSeen>() {
+        if let Ok((landmark, name)) = landmark_query.get(event.entity) {
+            logs.push(match name {
+                Some(name) => format!("{}: {}", name.0, landmark.description),
+                None => landmark.description.clone(),
+            });
+        }
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
@@ -0,0 +1,110 @@
+//! Wearing and wielding things, and what that does to combat.
+
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::resources::*;
+
+/// Base stats plus the bonuses from everything equipped. This is what combat should look at;
+/// `CombatStats` on its own is just the entity without any gear.
+pub fn effective_stats(
+    base: CombatStats,
+    inventory: Option<&Inventory>,
+    equipment_query: &Query<&Equippable, With<Equipped>>,
+) -> CombatStats {
+    let mut out = base;
+
+    for item in inventory.iter().flat_map(|inv| inv.items.iter()) {
+        if let Ok(equippable) = equipment_query.get(*item) {
+            out.power += equippable.power_bonus;
+            out.defense += equippable.defense_bonus;
+        }
+    }
+
+    out
+}
+
+/// Equipping or unequipping takes a turn either way. Equipping something into a slot that's
+/// already taken takes the old thing off first.
+pub fn process_equipment_events(
+    mut commands: Commands,
+    inventory_query: Query<&Inventory>,
+    equipped_query: Query<&Equippable, With<Equipped>>,
+    equippable_query: Query<&Equippable>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut finished = Vec::new();
+    let mut logs = Vec::new();
+
+    let name_of = |e: Entity| {
+        name_query
+            .get(e)
+            .map(|n| n.0.clone())
+            .unwrap_or_else(|_| "[unknown]".to_string())
+    };
+
+    for event in events.iter::<EntityUnequipsItem>() {
+        let EntityUnequipsItem { entity, item } = *event;
+
+        let carried = inventory_query
+            .get(entity)
+            .map_or(false, |inv| inv.items.contains(&item));
+        if !carried || equipped_query.get(item).is_err() {
+            continue;
+        }
+
+        commands.entity(item).remove::<Equipped>();
+        logs.push(format!("{} removes {}.", name_of(entity), name_of(item)));
+        finished.push(EntityFinishedTurn {
+            entity,
+            cost: ActionCost::Equip,
+        });
+    }
+
+    for event in events.iter::<EntityEquipsItem>() {
+        let EntityEquipsItem { entity, item } = *event;
+
+        let inventory = match inventory_query.get(entity) {
+            Ok(inv) if inv.items.contains(&item) => inv,
+            _ => continue,
+        };
+        let slot = match equippable_query.get(item) {
+            Ok(equippable) => equippable.slot,
+            Err(_) => continue,
+        };
+
+        for other in inventory.items.iter().copied() {
+            if other == item {
+                continue;
+            }
+            if let Ok(worn) = equipped_query.get(other) {
+                if worn.slot == slot {
+                    commands.entity(other).remove::<Equipped>();
+                    logs.push(format!("{} removes {}.", name_of(entity), name_of(other)));
+                }
+            }
+        }
+
+        commands.entity(item).insert(Equipped);
+        logs.push(format!(
+            "{} equips {} ({}).",
+            name_of(entity),
+            name_of(item),
+            slot.describe()
+        ));
+        finished.push(EntityFinishedTurn {
+            entity,
+            cost: ActionCost::Equip,
+        });
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+    for finish in finished {
+        events.send(finish);
+    }
+}
@@ -0,0 +1,199 @@
+//! Who's on whose side. Every faction starts out feeling some way about the player (see
+//! `factions.ron`), and that changes with what the player does: attacking and killing members of
+//! a faction sours it, killing its enemies sweetens it, and conversations can go either way.
+//! Individuals have their own feelings too, which win out once they're strong enough.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::raws::{FactionRegistry, PLAYER_FACTION};
+use crate::resources::*;
+
+/// Regard or standing at least this high makes for friends, whatever the factions say
+const FRIENDLY_AT: i32 = 20;
+/// ... and this low makes for enemies
+const HOSTILE_AT: i32 = -20;
+
+/// What someone who turns on the player thinks of them from then on
+pub const GRUDGE: i32 = -100;
+
+// How much each thing the player does is worth
+const ATTACK_REGARD: i32 = -30;
+const ATTACK_STANDING: i32 = -5;
+const KILL_STANDING: i32 = -10;
+/// For every faction that was hostile to whoever got killed
+const KILL_ENEMY_STANDING: i32 = 5;
+
+fn strong_feeling(value: i32) -> Option<Reaction> {
+    if value >= FRIENDLY_AT {
+        Some(Reaction::Friendly)
+    } else if value <= HOSTILE_AT {
+        Some(Reaction::Hostile)
+    } else {
+        None
+    }
+}
+
+/// How someone feels about the player: their own regard if it's strong enough, then their
+/// faction's standing if that is, and otherwise just how their faction feels about the player
+/// in general. Anyone without a faction is hostile, same as before there were factions at all.
+pub fn reaction_to_player(
+    registry: &FactionRegistry,
+    standings: &FactionStandings,
+    faction: Option<&Faction>,
+    regard: Option<&Regard>,
+) -> Reaction {
+    if let Some(reaction) = strong_feeling(regard.map(|r| r.0).unwrap_or(0)) {
+        return reaction;
+    }
+
+    let faction = match faction {
+        Some(faction) => faction,
+        None => return Reaction::Hostile,
+    };
+
+    strong_feeling(standings.get(&faction.0))
+        .unwrap_or_else(|| registry.reaction(&faction.0, PLAYER_FACTION))
+}
+
+/// Everything needed to ask how some entity feels about the player
+#[derive(SystemParam)]
+pub struct Reactions<'w, 's> {
+    registry: Res<'w, FactionRegistry>,
+    standings: Res<'w, FactionStandings>,
+    query: Query<'w, 's, (Option<&'static Faction>, Option<&'static Regard>)>,
+}
+
+impl<'w, 's> Reactions<'w, 's> {
+    pub fn toward_player(&self, entity: Entity) -> Reaction {
+        let (faction, regard) = self.query.get(entity).unwrap_or((None, None));
+        reaction_to_player(&*self.registry, &*self.standings, faction, regard)
+    }
+}
+
+/// Applies whatever changed how people feel about the player this turn. Anyone friendly who
+/// ends up hostile over it stops talking and starts fighting.
+///
+/// Hurting someone only counts as an attack when it's the player's doing on the player's own
+/// turn, and only once a turn however many ways it hurt; poison the player left behind doesn't
+/// sour anyone further as it ticks. A kill always counts, whatever the player did it with.
+pub fn update_reputation(
+    mut events: ResMut<CallbackEvents>,
+    registry: Res<FactionRegistry>,
+    mut standings: ResMut<FactionStandings>,
+    turns: Res<TurnOrder>,
+    player_query: Query<Entity, With<Player>>,
+    faction_query: Query<&Faction>,
+    mut regard_query: Query<(Entity, &mut Regard, Option<&Faction>, Option<&Talker>)>,
+) {
+    let player = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let faction_of = |entity: Entity| faction_query.get(entity).ok().map(|f| f.0.clone());
+
+    let mut regard_changes: Vec<(Entity, i32)> = Vec::new();
+    let mut standing_changes: Vec<(String, i32)> = Vec::new();
+
+    let mut attacked: Vec<Entity> = Vec::new();
+    if turns.current_holder() == Some(player) {
+        attacked.extend(
+            events
+                .iter::<EntityMeleeAttacks>()
+                .filter(|e| e.attacker == player)
+                .map(|e| e.defender),
+        );
+        attacked.extend(
+            events
+                .iter::<EntitySuffersDamage>()
+                .filter(|e| e.source == Some(player))
+                .map(|e| e.entity),
+        );
+        attacked.extend(
+            events
+                .iter::<EntityGainsStatus>()
+                .filter(|e| e.source == Some(player) && e.effect.kind.is_harmful())
+                .map(|e| e.entity),
+        );
+        attacked.retain(|e| *e != player);
+        attacked.sort_by_key(|e| e.id());
+        attacked.dedup();
+    }
+
+    for defender in attacked {
+        regard_changes.push((defender, ATTACK_REGARD));
+        if let Some(faction) = faction_of(defender) {
+            standing_changes.push((faction, ATTACK_STANDING));
+        }
+    }
+
+    for event in events.iter::<EntityDies>() {
+        if event.killer != Some(player) {
+            continue;
+        }
+        if let Some(faction) = faction_of(event.entity) {
+            for other in registry.names() {
+                if other != faction && registry.reaction(other, &faction) == Reaction::Hostile {
+                    standing_changes.push((other.to_string(), KILL_ENEMY_STANDING));
+                }
+            }
+            standing_changes.push((faction, KILL_STANDING));
+        }
+    }
+
+    regard_changes.extend(
+        events
+            .iter::<EntityRegardChanged>()
+            .map(|e| (e.entity, e.amount)),
+    );
+    standing_changes.extend(
+        events
+            .iter::<FactionStandingChanged>()
+            .map(|e| (e.faction.clone(), e.amount)),
+    );
+
+    if regard_changes.is_empty() && standing_changes.is_empty() {
+        return;
+    }
+
+    let mut logs = Vec::new();
+    for (faction, amount) in standing_changes {
+        let before = strong_feeling(standings.get(&faction));
+        standings.add(&faction, amount);
+        let after = strong_feeling(standings.get(&faction));
+
+        if before != after {
+            logs.push(match after {
+                Some(Reaction::Friendly) => format!("The {} count you as a friend now.", faction),
+                Some(Reaction::Hostile) => format!("The {} count you as an enemy now.", faction),
+                _ => format!("The {} don't know what to make of you any more.", faction),
+            });
+        }
+    }
+
+    for (entity, amount) in regard_changes {
+        if let Ok((_, mut regard, _, _)) = regard_query.get_mut(entity) {
+            regard.0 += amount;
+        }
+    }
+
+    let turned: Vec<Entity> = regard_query
+        .iter()
+        .filter(|(_, _, _, talker)| talker.is_some())
+        .filter(|(_, regard, faction, _)| {
+            reaction_to_player(&*registry, &*standings, *faction, Some(*regard))
+                == Reaction::Hostile
+        })
+        .map(|(entity, _, _, _)| entity)
+        .collect();
+
+    for entity in turned {
+        events.send(EntityTurnsHostile { entity });
+    }
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
@@ -459,15 +459,21 @@ fn refresh_area(hero: WorldPos, range: f32, map: &Map) -> HashSet<WorldPos> {
 
 pub fn compute_viewsheds(
     mut events: ResMut<CallbackEvents>,
-    mut query: Query<(&mut Viewshed, &WorldPos)>,
+    mut query: Query<(&mut Viewshed, &WorldPos, Option<&StatusEffects>)>,
     map: Res<Map>,
 ) {
     let start = std::time::Instant::now();
     let mut visibility_events = Vec::new();
-    for moved_entity in events.iter::<EntityMovedEvent>().map(|e| e.entity) {
-        match query.get_mut(moved_entity) {
-            Ok((mut vs, wp)) => {
-                vs.visible_tiles = refresh_area(*wp, vs.range as f32, &*map);
+    // moving changes what's in view, and so can going blind (or getting over it)
+    let refreshed = events
+        .iter::<EntityMovedEvent>()
+        .map(|e| e.entity)
+        .chain(events.iter::<EntityStatusChanged>().map(|e| e.entity));
+    for refreshed_entity in refreshed {
+        match query.get_mut(refreshed_entity) {
+            Ok((mut vs, wp, effects)) => {
+                let range = effects.map_or(vs.range, |e| e.sight_range(vs.range));
+                vs.visible_tiles = refresh_area(*wp, range as f32, &*map);
 
                 // TODO perf: in theory we only need to send this for the player?
                 visibility_events.push(VisibilityChangedEvent);
@@ -477,9 +483,10 @@ pub fn compute_viewsheds(
         }
     }
     // this lets the initial viewsheds be populated
-    for (mut vs, wp) in query.iter_mut() {
+    for (mut vs, wp, effects) in query.iter_mut() {
         if vs.visible_tiles.is_empty() {
-            vs.visible_tiles = refresh_area(*wp, vs.range as f32, &*map);
+            let range = effects.map_or(vs.range, |e| e.sight_range(vs.range));
+            vs.visible_tiles = refresh_area(*wp, range as f32, &*map);
 
             // TODO perf: in theory we only need to send this for the player?
             visibility_events.push(VisibilityChangedEvent);
@@ -493,9 +500,15 @@ pub fn compute_viewsheds(
     bevy::log::debug!("FOV computations took {} ms", elapsed);
 }
 
+/// Things the player might see for the first time; once they have, they're marked `Seen`
+type UnseenFilter = (With<RequiresSeen>, Without<Seen>, Without<Player>);
+
 pub fn update_map_visibility(
+    mut commands: Commands,
     mut events: ResMut<CallbackEvents>,
     query: Query<&Viewshed, With<Player>>,
+    unseen_query: Query<(Entity, &WorldPos), UnseenFilter>,
+    unseen_landmark_query: Query<(Entity, &Landmark), Without<Seen>>,
     mut map: ResMut<Map>,
 ) {
     // Don't care about the details of the event, just that it occurred; we aren't doing "smart" updates
@@ -503,10 +516,35 @@ pub fn update_map_visibility(
         return;
     }
 
+    let mut first_seen = Vec::new();
     for vs in query.iter() {
         map.set_visible_exact(&vs.visible_tiles);
 
         // the map "changed" so we need to recompute the visual tiles and stuff
         events.send(MapChangedEvent);
+
+        first_seen.extend(
+            unseen_query
+                .iter()
+                .filter(|(_, wp)| vs.visible_tiles.contains(*wp))
+                .map(|(entity, _)| entity),
+        );
+        first_seen.extend(
+            unseen_landmark_query
+                .iter()
+                .filter(|(_, landmark)| {
+                    landmark
+                        .tiles
+                        .iter()
+                        .any(|wp| vs.visible_tiles.contains(wp))
+                })
+                .map(|(entity, _)| entity),
+        );
+    }
+
+    // only ever once; the marker is what remembers, across saves and level changes too
+    for entity in first_seen {
+        commands.entity(entity).insert(Seen);
+        events.send(EntityFirstSeen { entity });
     }
 }
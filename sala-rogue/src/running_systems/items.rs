@@ -0,0 +1,487 @@
+//! Picking things up and putting them down again. `handle_input` only decides that it should
+//! happen; these systems do the actual moving between the floor and inventories.
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::map::Map;
+use crate::raws::ItemRegistry;
+use crate::resources::*;
+use crate::running_systems::has_line_of_fire;
+
+/// How an item (or a stack of them) is referred to in the logs
+pub fn describe_item(name: Option<&EntityName>, item: &Item) -> String {
+    let name = name.map(|n| n.0.as_str()).unwrap_or("[unknown]");
+    if item.count > 1 {
+        format!("{} (x{})", name, item.count)
+    } else {
+        name.to_string()
+    }
+}
+
+pub fn process_item_pickup(
+    mut commands: Commands,
+    mut inventory_query: Query<&mut Inventory>,
+    mut item_query: Query<&mut Item>,
+    equippable_query: Query<(), With<Equippable>>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut logs = Vec::new();
+
+    for event in events.iter::<ItemPickedUp>() {
+        let ItemPickedUp { entity, item } = *event;
+
+        let mut inventory = match inventory_query.get_mut(entity) {
+            Ok(inv) => inv,
+            Err(_) => continue,
+        };
+        let picked: Item = match item_query.get(item) {
+            Ok(picked) => *picked,
+            Err(_) => continue,
+        };
+
+        let item_name = name_query.get(item).ok();
+        let description = describe_item(item_name, &picked);
+
+        // things with the same name stack up, rather than taking another slot; except equipment,
+        // which is worn one at a time
+        let stackable = equippable_query.get(item).is_err();
+        let same_kind = inventory.items.iter().copied().find(|other| {
+            stackable
+                && name_query.get(*other).ok() == item_name
+                && item_query.get(*other).map(|i| i.weight).ok() == Some(picked.weight)
+        });
+
+        match same_kind {
+            Some(existing) => {
+                if let Ok(mut stack) = item_query.get_mut(existing) {
+                    stack.count += picked.count;
+                }
+                commands.entity(item).despawn();
+            }
+            None => {
+                commands.entity(item).remove::<WorldPos>();
+                inventory.items.push(item);
+            }
+        }
+
+        let who = name_query
+            .get(entity)
+            .map(|n| n.0.as_str())
+            .unwrap_or("[unknown]");
+        logs.push(LogIssuedEvent {
+            log: Log {
+                message: format!("{} picks up {}.", who, description),
+            },
+        });
+    }
+
+    for log in logs {
+        events.send(log);
+    }
+}
+
+pub fn process_item_drop(
+    mut commands: Commands,
+    mut inventory_query: Query<(&mut Inventory, &WorldPos)>,
+    item_query: Query<&Item>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut logs = Vec::new();
+
+    for event in events.iter::<ItemDropped>() {
+        let ItemDropped { entity, item } = *event;
+
+        let (mut inventory, wp) = match inventory_query.get_mut(entity) {
+            Ok(tup) => tup,
+            Err(_) => continue,
+        };
+        let idx = match inventory.items.iter().position(|i| *i == item) {
+            Some(idx) => idx,
+            // can't drop what you don't have
+            None => continue,
+        };
+
+        inventory.items.remove(idx);
+        commands.entity(item).insert(*wp).remove::<Equipped>();
+
+        let description = match item_query.get(item) {
+            Ok(dropped) => describe_item(name_query.get(item).ok(), dropped),
+            Err(_) => "[unknown]".to_string(),
+        };
+        let who = name_query
+            .get(entity)
+            .map(|n| n.0.as_str())
+            .unwrap_or("[unknown]");
+        logs.push(LogIssuedEvent {
+            log: Log {
+                message: format!("{} drops {}.", who, description),
+            },
+        });
+    }
+
+    for log in logs {
+        events.send(log);
+    }
+}
+
+/// Items turning up in (or vanishing from) an inventory without ever touching the floor, like
+/// when someone hands something over. New things stack up the same way picked up ones do.
+pub fn process_item_gifts(
+    mut commands: Commands,
+    registry: Res<ItemRegistry>,
+    mut inventory_query: Query<&mut Inventory>,
+    mut item_query: Query<(&mut Item, &EntityName, Option<&Equippable>)>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut logs = Vec::new();
+    let who = |entity: Entity| {
+        name_query
+            .get(entity)
+            .map(|n| n.0.clone())
+            .unwrap_or_else(|_| "[unknown]".to_string())
+    };
+
+    for event in events.iter::<EntityGivenItem>() {
+        let mut inventory = match inventory_query.get_mut(event.entity) {
+            Ok(inv) => inv,
+            Err(_) => continue,
+        };
+        let def = match registry.get(&event.name) {
+            Some(def) => def,
+            None => {
+                bevy::log::warn!("Tried to give out {}, which isn't in items.ron", event.name);
+                continue;
+            }
+        };
+
+        // equipment is worn one at a time, so it doesn't stack
+        let stacks = match def.equippable {
+            Some(_) => vec![1; event.count as usize],
+            None => vec![event.count],
+        };
+        let existing = inventory.items.iter().copied().find(|other| {
+            item_query
+                .get(*other)
+                .map_or(false, |(_, name, equippable)| {
+                    def.equippable.is_none() && equippable.is_none() && name.0 == def.name
+                })
+        });
+
+        match existing.and_then(|e| item_query.get_mut(e).ok()) {
+            Some((mut stack, _, _)) => stack.count += event.count,
+            None => {
+                for count in stacks {
+                    let mut item = commands.spawn();
+                    item.insert(Item {
+                        weight: def.weight,
+                        count,
+                    })
+                    .insert(RequiresSeen)
+                    .insert(def.make_renderable())
+                    .insert(def.make_name());
+                    if let Some(consumable) = def.consumable.clone() {
+                        item.insert(consumable);
+                    }
+                    if let Some(equippable) = def.equippable {
+                        item.insert(equippable);
+                    }
+                    inventory.items.push(item.id());
+                }
+            }
+        }
+
+        let description = describe_item(
+            Some(&def.make_name()),
+            &Item {
+                weight: def.weight,
+                count: event.count,
+            },
+        );
+        logs.push(format!("{} receives {}.", who(event.entity), description));
+    }
+
+    for event in events.iter::<EntityLosesItem>() {
+        let mut inventory = match inventory_query.get_mut(event.entity) {
+            Ok(inv) => inv,
+            Err(_) => continue,
+        };
+
+        let mut remaining = event.count;
+        for item in inventory.items.clone() {
+            if remaining == 0 {
+                break;
+            }
+            let mut stack = match item_query.get_mut(item) {
+                Ok((stack, name, _)) if name.0 == event.name => stack,
+                _ => continue,
+            };
+
+            let taken = remaining.min(stack.count);
+            stack.count -= taken;
+            remaining -= taken;
+            if stack.count == 0 {
+                inventory.items.retain(|i| *i != item);
+                commands.entity(item).despawn();
+            }
+        }
+
+        let given = event.count - remaining;
+        if given > 0 {
+            let description = describe_item(
+                Some(&EntityName(event.name.clone())),
+                &Item {
+                    weight: 0.0,
+                    count: given,
+                },
+            );
+            logs.push(format!("{} hands over {}.", who(event.entity), description));
+        }
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
+
+type ItemUserQuery<'a> = (
+    &'a WorldPos,
+    &'a mut Inventory,
+    Option<&'a Viewshed>,
+    Option<&'a Player>,
+);
+
+type UsableItemQuery<'a> = (
+    &'a mut Item,
+    Option<&'a Consumable>,
+    Option<&'a Equippable>,
+    Option<&'a Equipped>,
+);
+
+/// Who's using what, and what everything involved is called
+#[derive(SystemParam)]
+pub struct ItemUseQueries<'w, 's> {
+    users: Query<'w, 's, ItemUserQuery<'static>>,
+    items: Query<'w, 's, UsableItemQuery<'static>>,
+    names: Query<'w, 's, &'static EntityName>,
+}
+
+/// Figures out who a used item affects, and turns its effects into the usual events (damage,
+/// healing, ...) for the systems further down the line to resolve. Using something only takes a
+/// turn if it actually worked. Equipment gets passed along to be put on or taken off.
+///
+/// When the player uses something which needs aiming, without saying where, this brings up the
+/// targeting cursor instead; the item gets used for real once a target is picked.
+pub fn process_item_use(
+    mut commands: Commands,
+    mut queries: ItemUseQueries,
+    map: Res<Map>,
+    combat_tiles: Res<CombatStatsTiles>,
+    mut targeting: ResMut<TargetingMode>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    // (target, user, what it does)
+    let mut results: Vec<(Entity, Entity, Consumable)> = Vec::new();
+    let mut equip_changes: Vec<(Entity, Entity, bool)> = Vec::new();
+    let mut finished = Vec::new();
+    let mut logs = Vec::new();
+
+    for event in events.iter::<EntityUsesItem>() {
+        let EntityUsesItem {
+            entity,
+            item,
+            target,
+        } = *event;
+
+        let (user_pos, mut inventory, viewshed, player) = match queries.users.get_mut(entity) {
+            Ok(tup) => tup,
+            Err(_) => continue,
+        };
+        if !inventory.items.contains(&item) {
+            continue;
+        }
+        let (mut stack, consumable, equippable, equipped) = match queries.items.get_mut(item) {
+            Ok(tup) => tup,
+            Err(_) => continue,
+        };
+
+        let who = queries
+            .names
+            .get(entity)
+            .map(|n| n.0.as_str())
+            .unwrap_or("[unknown]");
+        let item_name = queries
+            .names
+            .get(item)
+            .map(|n| n.0.as_str())
+            .unwrap_or("[unknown]");
+
+        // "using" a piece of equipment means putting it on, or taking it off
+        if equippable.is_some() {
+            equip_changes.push((entity, item, equipped.is_none()));
+            continue;
+        }
+
+        let consumable = match consumable {
+            Some(consumable) => consumable,
+            None => {
+                logs.push(LogIssuedEvent {
+                    log: Log {
+                        message: format!("{} can't be used.", item_name),
+                    },
+                });
+                continue;
+            }
+        };
+
+        let can_see = |wp: WorldPos| viewshed.map_or(true, |vs| vs.visible_tiles.contains(&wp));
+        let can_hit = |wp: WorldPos, range: i32| {
+            map.step_distance(wp, *user_pos) <= range
+                && can_see(wp)
+                && has_line_of_fire(&*map, *user_pos, wp)
+        };
+
+        // if nobody said where to aim, go for whoever is closest
+        let closest = |range: i32| -> Option<WorldPos> {
+            viewshed?
+                .visible_tiles
+                .iter()
+                .copied()
+                .filter(|wp| *wp != *user_pos && can_hit(*wp, range))
+                .filter(|wp| combat_tiles.has_any(*wp))
+                .min_by_key(|wp| (map.step_distance(*wp, *user_pos), wp.y, wp.x))
+        };
+        let aim = |range: i32| -> Option<WorldPos> {
+            match target {
+                // nobody aims at their own feet
+                Some(wp) => Some(wp).filter(|wp| *wp != *user_pos && can_hit(*wp, range)),
+                None => closest(range),
+            }
+        };
+
+        let aim_range = match consumable.targeting {
+            Targeting::User => None,
+            Targeting::Single { range } | Targeting::Area { range, .. } => Some(range),
+        };
+        if let (Some(range), None, Some(_)) = (aim_range, target, player) {
+            // no point aiming with nobody to aim at
+            let pos = match closest(range) {
+                Some(pos) => pos,
+                None => {
+                    logs.push(LogIssuedEvent {
+                        log: Log {
+                            message: format!("There is nothing in range to use {} on.", item_name),
+                        },
+                    });
+                    continue;
+                }
+            };
+            targeting.0 = Some(TargetingCursor {
+                purpose: TargetPurpose::UseItem { item },
+                pos,
+                range,
+            });
+            logs.push(LogIssuedEvent {
+                log: Log {
+                    message: format!(
+                        "Aim {} with the movement keys; enter to confirm, backspace to cancel.",
+                        item_name
+                    ),
+                },
+            });
+            continue;
+        }
+
+        let mut affected: Vec<Entity> = match consumable.targeting {
+            Targeting::User => vec![entity],
+            Targeting::Single { range } => aim(range)
+                .and_then(|wp| combat_tiles.get_any(wp))
+                .into_iter()
+                .collect(),
+            Targeting::Area { range, radius } => match aim(range) {
+                Some(center) => {
+                    let mut hit = Vec::new();
+                    for y in center.y - radius..=center.y + radius {
+                        for x in center.x - radius..=center.x + radius {
+                            let wp = WorldPos { x, y };
+                            if map.step_distance(wp, center) <= radius && can_see(wp) {
+                                hit.extend(combat_tiles.get_all(wp));
+                            }
+                        }
+                    }
+                    hit
+                }
+                None => Vec::new(),
+            },
+        };
+        affected.sort_by_key(|e| e.id());
+
+        if affected.is_empty() {
+            logs.push(LogIssuedEvent {
+                log: Log {
+                    message: format!("There is nothing in range to use {} on.", item_name),
+                },
+            });
+            continue;
+        }
+
+        logs.push(LogIssuedEvent {
+            log: Log {
+                message: format!("{} uses {}.", who, item_name),
+            },
+        });
+
+        for target in affected {
+            results.push((target, entity, consumable.clone()));
+        }
+
+        stack.count -= 1;
+        if stack.count == 0 {
+            inventory.items.retain(|i| *i != item);
+            commands.entity(item).despawn();
+        }
+
+        finished.push(EntityFinishedTurn {
+            entity,
+            cost: ActionCost::UseItem,
+        });
+    }
+
+    for log in logs {
+        events.send(log);
+    }
+    // whatever an item does is down to whoever used it
+    for (entity, user, consumable) in results {
+        for effect in consumable.effects {
+            match effect {
+                ItemEffect::Heal(amount) => events.send(EntityHealed { entity, amount }),
+                ItemEffect::Damage(damage) => events.send(EntitySuffersDamage {
+                    entity,
+                    damage,
+                    source: Some(user),
+                }),
+                ItemEffect::Inflict(effect) => events.send(EntityGainsStatus {
+                    entity,
+                    effect,
+                    source: Some(user),
+                }),
+            }
+        }
+    }
+    for (entity, item, equip) in equip_changes {
+        if equip {
+            events.send(EntityEquipsItem { entity, item });
+        } else {
+            events.send(EntityUnequipsItem { entity, item });
+        }
+    }
+    for finish in finished {
+        events.send(finish);
+    }
+}
@@ -0,0 +1,558 @@
+//! Monster decision making. What a monster knows about the player (its `Awareness`) is kept up to
+//! date here: monsters notice the player when they can see them, head for wherever they last saw
+//! them when they can't, poke around there for a while, and eventually give up.
+//!
+//! The player isn't the only one who can get into a fight: a monster that's chasing will also go
+//! after anyone it can see from a faction its own is hostile to. Nobody keeps track of those,
+//! though; out of sight is out of mind.
+//!
+//! What a monster actually does about it is up to its `AiBehaviors`. Each behavior looks at the
+//! situation and suggests an action with a score; the highest score wins, as long as no behavior
+//! objects to it. Actions are only ever sent out as events; `apply_moves` does the moving.
+
+use std::cmp::Ordering;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use super::dijkstra;
+use super::factions::{Reactions, GRUDGE};
+use super::pathfinding;
+use super::status;
+use crate::components::*;
+use crate::map::Map;
+use crate::raws::FactionRegistry;
+use crate::resources::*;
+
+/// How many turns a monster looks around the last known position before giving up
+const SEARCH_TURNS: u32 = 8;
+
+/// How far from the last known position a searching monster is willing to wander
+const SEARCH_RADIUS: i32 = 4;
+
+// How pressing each suggestion is. Running for your life beats everything; idle wandering loses
+// to anything.
+const FLEE_SCORE: f32 = 0.9;
+const RETURN_TO_POST_SCORE: f32 = 0.8;
+const KEEP_DISTANCE_SCORE: f32 = 0.7;
+const ATTACK_SCORE: f32 = 0.6;
+const CHASE_SCORE: f32 = 0.5;
+const FOLLOW_TRAIL_SCORE: f32 = 0.4;
+const SEARCH_SCORE: f32 = 0.3;
+const WANDER_SCORE: f32 = 0.1;
+
+// Which Dijkstra maps each kind of movement follows, and how strongly
+const CHASE_GOALS: &[(DijkstraGoal, f32)] = &[(DijkstraGoal::TowardPlayer, 1.0)];
+const BACK_OFF_GOALS: &[(DijkstraGoal, f32)] = &[(DijkstraGoal::FleePlayer, 1.0)];
+
+/// Running away; toward friends too, as much as the monster's `FleeWhenHurt` says
+fn flee_goals(toward_allies: f32) -> [(DijkstraGoal, f32); 2] {
+    [
+        (DijkstraGoal::FleePlayer, 1.0),
+        (DijkstraGoal::TowardAllies, toward_allies),
+    ]
+}
+
+/// Everyone who's on a side, and could be fought
+type FactionQuery<'a> = (Entity, &'a WorldPos, &'a Faction, Option<&'a MonsterAI>);
+
+type MonsterQuery<'a> = (
+    &'a Viewshed,
+    &'a WorldPos,
+    &'a mut Awareness,
+    &'a AiBehaviors,
+    Option<&'a CombatStats>,
+    Option<&'a EntityName>,
+);
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum AiAction {
+    Attack(Entity),
+    Step(WorldPos),
+    Wait,
+}
+
+/// Everything a behavior gets to look at when making up its mind
+struct AiContext<'a> {
+    pos: WorldPos,
+    home: WorldPos,
+    awareness: Awareness,
+    /// The player, if this monster can see them
+    player_seen: Option<(Entity, WorldPos)>,
+    /// The closest enemy of this monster's faction it can see, not counting the player
+    rival_seen: Option<(Entity, WorldPos)>,
+    hp_fraction: f32,
+    /// Who's deciding, so they don't count themselves as their own ally
+    me: Entity,
+    faction: Option<&'a str>,
+    map: &'a Map,
+    blocked: &'a BlockedTiles,
+    maps: &'a DijkstraMaps,
+}
+
+impl<'a> AiContext<'a> {
+    fn open_neighbors(&self) -> Vec<WorldPos> {
+        self.map
+            .adjacent(self.pos)
+            .filter(|tile| !self.blocked.has_any(*tile))
+            .collect()
+    }
+
+    /// The open neighbor with the lowest cost, if any of them is actually cheaper than where
+    /// we're standing. Tiles without a cost are never worth stepping on.
+    fn downhill<F: Fn(WorldPos) -> Option<f32>>(&self, cost: F) -> Option<WorldPos> {
+        let mut best = None;
+        let mut best_cost = cost(self.pos).unwrap_or(f32::MAX);
+        for tile in self.open_neighbors() {
+            if let Some(tile_cost) = cost(tile) {
+                if tile_cost < best_cost {
+                    best_cost = tile_cost;
+                    best = Some(tile);
+                }
+            }
+        }
+        best
+    }
+
+    /// Follow a weighted mix of the shared Dijkstra maps
+    fn follow(&self, goals: &[(DijkstraGoal, f32)]) -> Option<WorldPos> {
+        self.downhill(|wp| self.maps.combined_cost(goals, wp, self.me, self.faction))
+    }
+
+    /// The first step toward somewhere only this monster is headed. Goes around whatever's in
+    /// the way if it can; otherwise heads straight on, in case it's moved by the time it matters.
+    fn step_toward(&self, goal: WorldPos) -> Option<WorldPos> {
+        let path = pathfinding::find_path(self.map, self.blocked, self.pos, goal)
+            .or_else(|| pathfinding::a_star(self.map, self.pos, goal, |_| false))?;
+        path.first()
+            .copied()
+            .filter(|tile| !self.blocked.has_any(*tile))
+    }
+}
+
+impl Behavior {
+    /// What this behavior would like to do this turn, and how badly
+    fn propose<R: Rng>(&self, ctx: &AiContext, rng: &mut R) -> Option<(f32, AiAction)> {
+        match *self {
+            Behavior::Chase => match (ctx.awareness, ctx.player_seen, ctx.rival_seen) {
+                (Awareness::Hunting { .. }, Some((player, player_pos)), _) => {
+                    if ctx.map.is_adjacent(ctx.pos, player_pos) {
+                        Some((ATTACK_SCORE, AiAction::Attack(player)))
+                    } else {
+                        let step = ctx.follow(CHASE_GOALS)?;
+                        Some((CHASE_SCORE, AiAction::Step(step)))
+                    }
+                }
+                // someone right here beats a player who isn't
+                (_, None, Some((rival, rival_pos))) => {
+                    if ctx.map.is_adjacent(ctx.pos, rival_pos) {
+                        Some((ATTACK_SCORE, AiAction::Attack(rival)))
+                    } else {
+                        let step = ctx.step_toward(rival_pos)?;
+                        Some((CHASE_SCORE, AiAction::Step(step)))
+                    }
+                }
+                (Awareness::Hunting { last_seen, .. }, None, None) => {
+                    let step = ctx.step_toward(last_seen)?;
+                    Some((FOLLOW_TRAIL_SCORE, AiAction::Step(step)))
+                }
+                (Awareness::Searching { last_seen, .. }, _, _) => {
+                    let options: Vec<WorldPos> = ctx
+                        .open_neighbors()
+                        .into_iter()
+                        .filter(|tile| ctx.map.step_distance(*tile, last_seen) <= SEARCH_RADIUS)
+                        .collect();
+                    let step = options.choose(rng)?;
+                    Some((SEARCH_SCORE, AiAction::Step(*step)))
+                }
+                (Awareness::Idle, _, _) => None,
+            },
+            Behavior::FleeWhenHurt {
+                below,
+                toward_allies,
+            } => {
+                ctx.player_seen?;
+                if ctx.hp_fraction >= below {
+                    return None;
+                }
+                // cornered monsters fight, since there's nothing to suggest here
+                let step = ctx.follow(&flee_goals(toward_allies))?;
+                Some((FLEE_SCORE, AiAction::Step(step)))
+            }
+            Behavior::KeepDistance { range } => {
+                let (_, player_pos) = ctx.player_seen?;
+                match ctx.map.step_distance(ctx.pos, player_pos).cmp(&range) {
+                    Ordering::Less => {
+                        let step = ctx.follow(BACK_OFF_GOALS)?;
+                        Some((KEEP_DISTANCE_SCORE, AiAction::Step(step)))
+                    }
+                    Ordering::Equal => Some((KEEP_DISTANCE_SCORE, AiAction::Wait)),
+                    // too far away is for other behaviors to worry about
+                    Ordering::Greater => None,
+                }
+            }
+            Behavior::Guard { radius } => {
+                if ctx.map.step_distance(ctx.pos, ctx.home) <= radius {
+                    return None;
+                }
+                let step = ctx.step_toward(ctx.home)?;
+                Some((RETURN_TO_POST_SCORE, AiAction::Step(step)))
+            }
+            // this one is a reaction to noticing the player, not something to do with a turn
+            Behavior::CallAllies { .. } => None,
+            Behavior::Wander { chance } => {
+                if ctx.awareness != Awareness::Idle || !rng.gen_bool(chance as f64) {
+                    return None;
+                }
+                let step = ctx.open_neighbors().choose(rng).copied()?;
+                Some((WANDER_SCORE, AiAction::Step(step)))
+            }
+        }
+    }
+
+    /// Whether this behavior is willing to let the monster do that
+    fn allows(&self, ctx: &AiContext, action: AiAction) -> bool {
+        match (*self, action) {
+            // guards may not leave their post, but may always head back toward it
+            (Behavior::Guard { radius }, AiAction::Step(tile)) => {
+                let from_home = |wp: WorldPos| ctx.map.step_distance(wp, ctx.home);
+                from_home(tile) <= radius || from_home(tile) < from_home(ctx.pos)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Everyone a monster might have to reckon with, other than itself
+#[derive(SystemParam)]
+pub struct AiCreatures<'w, 's> {
+    player_query: Query<'w, 's, (Entity, &'static WorldPos), With<Player>>,
+    faction_query: Query<'w, 's, FactionQuery<'static>, With<CombatStats>>,
+    effects_query: Query<'w, 's, &'static StatusEffects>,
+}
+
+/// The lay of the land: the level, what's in the way, the shared Dijkstra maps, and who's on
+/// whose side
+#[derive(SystemParam)]
+pub struct AiSurroundings<'w, 's> {
+    map: Res<'w, Map>,
+    blocked: Res<'w, BlockedTiles>,
+    maps: ResMut<'w, DijkstraMaps>,
+    factions: Res<'w, FactionRegistry>,
+    reactions: Reactions<'w, 's>,
+}
+
+pub fn monster_ai(
+    mut monster_query: Query<MonsterQuery, With<MonsterAI>>,
+    creatures: AiCreatures,
+    surroundings: AiSurroundings,
+    turns: Res<TurnOrder>,
+    turn_number: Res<CurrentTurnNumber>,
+    mut rng: ResMut<GameRng>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let AiCreatures {
+        player_query,
+        faction_query,
+        effects_query,
+    } = creatures;
+    let AiSurroundings {
+        map,
+        blocked,
+        mut maps,
+        factions,
+        reactions,
+    } = surroundings;
+
+    let entity = match turns.current_holder() {
+        Some(entity) => entity,
+        None => return,
+    };
+
+    if monster_query.get(entity).is_err() {
+        return;
+    }
+
+    let (player_entity, player_pos) = match player_query.iter().next() {
+        Some((entity, wp)) => (entity, *wp),
+        // no player no action, but the game still has to move on
+        None => {
+            events.send(EntityFinishedTurn {
+                entity,
+                cost: ActionCost::Wait,
+            });
+            return;
+        }
+    };
+
+    let (vs, wp, mut awareness, brain, stats, name) = match monster_query.get_mut(entity) {
+        Ok(tup) => tup,
+        Err(e) => unreachable!("We know the entity matches the query; error was {:?}", e),
+    };
+
+    // only enemies go after the player; anyone else leaves them be
+    let sees_player = reactions.toward_player(entity) == Reaction::Hostile
+        && vs.visible_tiles.contains(&player_pos);
+    let mut next = if sees_player {
+        Awareness::Hunting {
+            last_seen: player_pos,
+            seen_turn: turn_number.0,
+        }
+    } else {
+        forget_a_little(*awareness, *wp)
+    };
+
+    let hp_fraction = stats
+        .map(|cs| cs.hp as f32 / cs.max_hp.max(1) as f32)
+        .unwrap_or(1.0);
+
+    // only worked out for monsters that are actually about to run to their friends
+    let might_flee = sees_player
+        && brain.behaviors.iter().any(|b| match b {
+            Behavior::FleeWhenHurt {
+                below,
+                toward_allies,
+            } => hp_fraction < *below && *toward_allies != 0.0,
+            _ => false,
+        });
+    let own_faction = faction_query
+        .get(entity)
+        .ok()
+        .map(|(_, _, f, _)| f.0.clone());
+    if let (true, Some(faction)) = (might_flee, own_faction.as_ref()) {
+        maps.build_allies(faction, || {
+            // only other monsters, since the map is only kept up to date as they move and die
+            let allies = faction_query
+                .iter()
+                .filter(|(_, _, other, monster)| {
+                    monster.is_some() && factions.reaction(faction, &other.0) == Reaction::Friendly
+                })
+                .map(|(ally, wp, _, _)| (ally, *wp));
+            dijkstra::nearest_two_map(&*map, allies)
+        });
+    }
+
+    let rival_seen = own_faction.as_ref().and_then(|faction| {
+        faction_query
+            .iter()
+            .filter(|(_, rival_pos, _, _)| vs.visible_tiles.contains(rival_pos))
+            .filter(|(_, _, other, _)| factions.reaction(faction, &other.0) == Reaction::Hostile)
+            .map(|(rival, rival_pos, _, _)| (rival, *rival_pos))
+            .min_by_key(|(rival, rival_pos)| (map.step_distance(*wp, *rival_pos), rival.id()))
+    });
+
+    let ctx = AiContext {
+        pos: *wp,
+        home: brain.home,
+        awareness: next,
+        player_seen: if sees_player {
+            Some((player_entity, player_pos))
+        } else {
+            None
+        },
+        rival_seen,
+        hp_fraction,
+        me: entity,
+        faction: own_faction.as_deref(),
+        map: &*map,
+        blocked: &*blocked,
+        maps: &*maps,
+    };
+
+    let rng = &mut rng.0;
+
+    // first suggestion wins ties, so the order behaviors are listed in matters a little
+    let mut action = AiAction::Wait;
+    let mut best_score = f32::MIN;
+    for (score, proposed) in brain
+        .behaviors
+        .iter()
+        .filter_map(|behavior| behavior.propose(&ctx, rng))
+    {
+        let allowed = brain.behaviors.iter().all(|b| b.allows(&ctx, proposed));
+        if allowed && score > best_score {
+            best_score = score;
+            action = proposed;
+        }
+    }
+
+    // confused monsters don't always manage what they were going for
+    if action != AiAction::Wait && status::staggers(effects_query.get(entity).ok(), rng) {
+        action = match ctx.open_neighbors().choose(rng) {
+            Some(stumble) => AiAction::Step(*stumble),
+            None => AiAction::Wait,
+        };
+    }
+
+    let cost = match action {
+        AiAction::Attack(defender) => {
+            events.send(EntityMeleeAttacks {
+                attacker: entity,
+                defender,
+            });
+            ActionCost::Attack
+        }
+        AiAction::Step(new_pos) => {
+            events.send(EntityMovedEvent {
+                entity,
+                old_pos: *wp,
+                new_pos,
+            });
+            ActionCost::Move
+        }
+        AiAction::Wait => ActionCost::Wait,
+    };
+    events.send(EntityFinishedTurn { entity, cost });
+
+    // couldn't make any headway along the trail (someone's in the way, or it's off limits);
+    // start looking around from here instead
+    if let Awareness::Hunting {
+        last_seen,
+        seen_turn,
+    } = next
+    {
+        if !sees_player && action == AiAction::Wait {
+            next = Awareness::Searching {
+                last_seen,
+                seen_turn,
+                turns_left: SEARCH_TURNS,
+            };
+        }
+    }
+
+    let name = name
+        .map(|n| n.0.clone())
+        .unwrap_or_else(|| "[unknown]".to_string());
+    let just_noticed = matches!(next, Awareness::Hunting { .. })
+        && !matches!(*awareness, Awareness::Hunting { .. });
+
+    if std::mem::discriminant(&next) != std::mem::discriminant(&*awareness) {
+        bevy::log::debug!(
+            "{} went from {} to {}",
+            name,
+            awareness.describe(),
+            next.describe()
+        );
+
+        let message = match next {
+            Awareness::Hunting { .. } => Some(format!("{} notices you!", name)),
+            Awareness::Searching { .. } => None,
+            Awareness::Idle => Some(format!("{} gives up the search.", name)),
+        };
+        if let Some(message) = message {
+            events.send(LogIssuedEvent {
+                log: Log { message },
+            });
+        }
+    }
+
+    *awareness = next;
+
+    let call_radius = brain
+        .behaviors
+        .iter()
+        .filter_map(|b| match b {
+            Behavior::CallAllies { radius } => Some(*radius),
+            _ => None,
+        })
+        .max();
+    let caller_pos = *wp;
+
+    if let (true, Some(radius)) = (just_noticed, call_radius) {
+        events.send(LogIssuedEvent {
+            log: Log {
+                message: format!("{} shouts for help!", name),
+            },
+        });
+
+        // only those who'd count the caller a friend come running
+        let allies = faction_query
+            .iter()
+            .filter(|(_, ally_pos, _, _)| map.step_distance(**ally_pos, caller_pos) <= radius)
+            .filter(|(_, _, other, _)| {
+                own_faction.as_ref().map_or(false, |faction| {
+                    factions.reaction(&other.0, faction) == Reaction::Friendly
+                })
+            })
+            .map(|(ally, _, _, _)| ally);
+        for ally in allies {
+            if let Ok((_, _, mut ally_awareness, _, _, _)) = monster_query.get_mut(ally) {
+                if !matches!(*ally_awareness, Awareness::Hunting { .. }) {
+                    *ally_awareness = next;
+                }
+            }
+        }
+    }
+}
+
+/// Someone friendly has had enough of the player. From here on they're a monster like any other,
+/// and they know exactly where the player is.
+pub fn turn_hostile(
+    mut commands: Commands,
+    player_query: Query<&WorldPos, With<Player>>,
+    npc_query: Query<(&WorldPos, Option<&EntityName>)>,
+    turn_number: Res<CurrentTurnNumber>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut logs = Vec::new();
+
+    for event in events.iter::<EntityTurnsHostile>() {
+        let (wp, name) = match npc_query.get(event.entity) {
+            Ok(tup) => tup,
+            Err(_) => continue,
+        };
+
+        let mut npc = commands.entity(event.entity);
+        npc.remove::<Talker>()
+            .insert(MonsterAI)
+            .insert(AiBehaviors {
+                behaviors: vec![Behavior::Chase],
+                home: *wp,
+            })
+            .insert(WantsTurnOrderAssignment)
+            .insert(Regard(GRUDGE));
+        if let Ok(player_pos) = player_query.get_single() {
+            npc.insert(Awareness::Hunting {
+                last_seen: *player_pos,
+                seen_turn: turn_number.0,
+            });
+        }
+
+        let name = name.map(|n| n.0.as_str()).unwrap_or("[unknown]");
+        logs.push(format!("{} attacks!", name));
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
+
+/// What a monster that can't see the player thinks, one turn later
+fn forget_a_little(awareness: Awareness, wp: WorldPos) -> Awareness {
+    match awareness {
+        Awareness::Idle => Awareness::Idle,
+        Awareness::Hunting {
+            last_seen,
+            seen_turn,
+        } if wp == last_seen => Awareness::Searching {
+            last_seen,
+            seen_turn,
+            turns_left: SEARCH_TURNS,
+        },
+        Awareness::Hunting { .. } => awareness,
+        Awareness::Searching { turns_left: 0, .. } => Awareness::Idle,
+        Awareness::Searching {
+            last_seen,
+            seen_turn,
+            turns_left,
+        } => Awareness::Searching {
+            last_seen,
+            seen_turn,
+            turns_left: turns_left - 1,
+        },
+    }
+}
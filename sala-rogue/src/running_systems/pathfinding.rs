@@ -0,0 +1,73 @@
+//! Point to point pathfinding. Dijkstra maps (see `dijkstra`) are the thing to use when lots of
+//! things want to get to the same place; this is for one thing going to one place.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::components::*;
+use crate::map::Map;
+use crate::resources::*;
+
+/// Shortest path from `start` to `goal` by A*, stepping between the same tiles `Map::adjacent`
+/// does. The path doesn't include the start, but does include the goal. None if there's no way
+/// through.
+pub fn a_star<IsBlocked: Fn(WorldPos) -> bool>(
+    map: &Map,
+    start: WorldPos,
+    goal: WorldPos,
+    is_blocked: IsBlocked,
+) -> Option<Vec<WorldPos>> {
+    if start == goal {
+        return Some(Vec::new());
+    }
+
+    let mut came_from: HashMap<WorldPos, WorldPos> = HashMap::new();
+    let mut cost_so_far: HashMap<WorldPos, i32> = HashMap::new();
+    // ordered by estimated total cost; ties broken by position, so the same map always gives the
+    // same path
+    let mut to_process = BinaryHeap::new();
+
+    cost_so_far.insert(start, 0);
+    to_process.push(Reverse((map.step_distance(start, goal), start.x, start.y)));
+
+    while let Some(Reverse((_, x, y))) = to_process.pop() {
+        let wp = WorldPos { x, y };
+        if wp == goal {
+            let mut path = vec![goal];
+            let mut curr = goal;
+            while let Some(prev) = came_from.get(&curr).copied() {
+                if prev == start {
+                    break;
+                }
+                path.push(prev);
+                curr = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cost = cost_so_far[&wp];
+        for next in map.adjacent(wp).filter(|tile| !is_blocked(*tile)) {
+            let next_cost = cost + 1;
+            if cost_so_far.get(&next).map_or(true, |c| next_cost < *c) {
+                cost_so_far.insert(next, next_cost);
+                came_from.insert(next, wp);
+                let estimate = next_cost + map.step_distance(next, goal);
+                to_process.push(Reverse((estimate, next.x, next.y)));
+            }
+        }
+    }
+
+    None
+}
+
+/// A* that steers around anything in `BlockedTiles`, except on the starting tile (where whoever
+/// wants the path is presumably standing)
+pub fn find_path(
+    map: &Map,
+    blocked: &BlockedTiles,
+    start: WorldPos,
+    goal: WorldPos,
+) -> Option<Vec<WorldPos>> {
+    a_star(map, start, goal, |wp| wp != start && blocked.has_any(wp))
+}
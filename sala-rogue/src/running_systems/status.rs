@@ -0,0 +1,166 @@
+//! Lingering effects, like poison. Anything can put a status effect on a creature by sending
+//! `EntityGainsStatus`; a new dose stacks onto whatever's already there (see `Stacking`). Effects
+//! do their thing and wear off a turn at a time, where a turn is a trip around the turn order, so
+//! fast creatures don't get through their poison any quicker.
+//!
+//! Most effects are looked up where they matter rather than handled here: slowness when working
+//! out how long an action takes, blindness when working out what can be seen, and confusion when
+//! deciding where a step actually goes.
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::components::*;
+use crate::resources::*;
+
+/// Odds that a confused creature's step goes somewhere it didn't mean it to
+const STAGGER_CHANCE: f64 = 0.5;
+
+/// Whether a creature with these effects stumbles this step, instead of going where it meant to
+pub fn staggers<R: Rng>(effects: Option<&StatusEffects>, rng: &mut R) -> bool {
+    effects.map_or(false, |e| e.has(StatusKind::Confused)) && rng.gen_bool(STAGGER_CHANCE)
+}
+
+pub fn apply_status_effects(
+    mut effects_query: Query<(&mut StatusEffects, &CombatStats)>,
+    name_query: Query<&EntityName>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    let mut changed = Vec::new();
+    let mut logs = Vec::new();
+
+    for event in events.iter::<EntityGainsStatus>() {
+        let EntityGainsStatus {
+            entity,
+            effect,
+            source,
+        } = *event;
+        if effect.turns == 0 {
+            continue;
+        }
+        let effect = StatusEffect { source, ..effect };
+
+        let mut effects = match effects_query.get_mut(entity) {
+            // the dead don't care
+            Ok((effects, cs)) if cs.hp > 0 => effects,
+            _ => continue,
+        };
+
+        let name = name_query
+            .get(entity)
+            .map(|n| n.0.as_str())
+            .unwrap_or("[unknown]");
+        let what = effect.kind.describe();
+
+        match effects.0.iter_mut().find(|e| e.kind == effect.kind) {
+            // worn off, just not cleared out yet; this is a fresh start
+            Some(existing) if existing.turns == 0 => {
+                *existing = effect;
+                logs.push(format!("{} is {}.", name, what));
+            }
+            Some(existing) => {
+                *existing = existing.stacked_with(effect);
+                logs.push(match effect.kind.stacking() {
+                    Stacking::Intensity => format!("{} is {} more heavily.", name, what),
+                    Stacking::Duration | Stacking::Refresh => {
+                        format!("{} is {} for longer.", name, what)
+                    }
+                });
+            }
+            None => {
+                effects.0.push(effect);
+                logs.push(format!("{} is {}.", name, what));
+            }
+        }
+
+        changed.push(EntityStatusChanged { entity });
+    }
+
+    for event in changed {
+        events.send(event);
+    }
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
+
+/// Once a turn, when the turn counter comes around, every effect does whatever it does and gets a
+/// turn closer to wearing off
+pub fn tick_status_effects(
+    turn: Res<TurnOrder>,
+    trigger_query: Query<(), With<EndOfTurnTrigger>>,
+    mut effects_query: Query<(Entity, &mut StatusEffects, Option<&EntityName>)>,
+    mut events: ResMut<CallbackEvents>,
+) {
+    match turn.current_holder() {
+        Some(holder) if trigger_query.get(holder).is_ok() => {}
+        _ => return,
+    }
+
+    let mut damage = Vec::new();
+    let mut healing = Vec::new();
+    let mut changed = Vec::new();
+    let mut logs = Vec::new();
+
+    for (entity, mut effects, name) in effects_query.iter_mut() {
+        // plenty of things never have anything going on, and shouldn't look changed every turn
+        if effects.0.is_empty() {
+            continue;
+        }
+
+        // anything that ran out last turn is cleared out now, rather than right after its last go,
+        // so whatever it did on that go can still be traced back to it
+        let name = name.map(|n| n.0.as_str()).unwrap_or("[unknown]");
+        let before = effects.0.len();
+        effects.0.retain(|effect| {
+            if effect.turns == 0 {
+                logs.push(format!("{} is no longer {}.", name, effect.kind.describe()));
+                false
+            } else {
+                true
+            }
+        });
+        if effects.0.len() != before {
+            changed.push(EntityStatusChanged { entity });
+        }
+
+        for effect in effects.0.iter_mut() {
+            match effect.kind {
+                StatusKind::Poisoned | StatusKind::Bleeding if effect.potency > 0 => {
+                    damage.push(EntitySuffersDamage {
+                        entity,
+                        damage: effect.potency,
+                        source: effect.source,
+                    })
+                }
+                StatusKind::Regenerating if effect.potency > 0 => healing.push(EntityHealed {
+                    entity,
+                    amount: effect.potency,
+                }),
+                _ => {}
+            }
+
+            effect.turns -= 1;
+            if effect.turns == 0 {
+                changed.push(EntityStatusChanged { entity });
+            }
+        }
+    }
+
+    for event in damage {
+        events.send(event);
+    }
+    for event in healing {
+        events.send(event);
+    }
+    for event in changed {
+        events.send(event);
+    }
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
@@ -0,0 +1,284 @@
+//! The story so far: flags, quests and endings. Flags get set by dialogue and by finishing quest
+//! stages; quests move along as their conditions are met; and as soon as any ending's conditions
+//! all hold, the game is over.
+
+use std::collections::HashMap;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use super::GameOverTrigger;
+use crate::components::*;
+use crate::raws::{EndingRegistry, QuestDef, QuestRegistry};
+use crate::resources::*;
+
+/// Everything about the player and the story that conditions can check
+pub struct StoryContext<'a> {
+    stats: Option<CombatStats>,
+    flags: &'a StoryFlags,
+    standings: &'a FactionStandings,
+    depth: u32,
+    /// How many of each thing (by name) the player has, across all their stacks
+    carried: HashMap<String, u32>,
+}
+
+impl<'a> StoryContext<'a> {
+    fn new(
+        stats: Option<CombatStats>,
+        inventory: Option<&Inventory>,
+        item_query: &Query<(&Item, &EntityName)>,
+        flags: &'a StoryFlags,
+        standings: &'a FactionStandings,
+        depth: u32,
+    ) -> Self {
+        let mut carried = HashMap::new();
+        for item in inventory.iter().flat_map(|inv| inv.items.iter()) {
+            if let Ok((item, name)) = item_query.get(*item) {
+                *carried.entry(name.0.clone()).or_insert(0) += item.count;
+            }
+        }
+
+        StoryContext {
+            stats,
+            flags,
+            standings,
+            depth,
+            carried,
+        }
+    }
+
+    pub fn holds(&self, condition: &StoryCondition) -> bool {
+        let stat = |stat: Stat| self.stats.map(|cs| stat.of(&cs)).unwrap_or(0);
+        let count = |name: &str| self.carried.get(name).copied().unwrap_or(0);
+
+        match condition {
+            StoryCondition::StatAtLeast { stat: s, value } => stat(*s) >= *value,
+            StoryCondition::StatBelow { stat: s, value } => stat(*s) < *value,
+            StoryCondition::HasFlag(flag) => self.flags.is_set(flag),
+            StoryCondition::LacksFlag(flag) => !self.flags.is_set(flag),
+            StoryCondition::CounterAtLeast { counter, value } => self.flags.get(counter) >= *value,
+            StoryCondition::HasItem { name, count: n } => count(name) >= *n,
+            StoryCondition::LacksItem(name) => count(name) == 0,
+            StoryCondition::DepthAtLeast(depth) => self.depth >= *depth,
+            StoryCondition::StandingAtLeast { faction, value } => {
+                self.standings.get(faction) >= *value
+            }
+            StoryCondition::StandingBelow { faction, value } => {
+                self.standings.get(faction) < *value
+            }
+        }
+    }
+
+    pub fn all_hold(&self, conditions: &[StoryCondition]) -> bool {
+        conditions.iter().all(|c| self.holds(c))
+    }
+}
+
+/// Everything about the player that quests and endings care about
+type StoryPlayerQuery<'a> = (Entity, Option<&'a CombatStats>, Option<&'a Inventory>);
+
+/// Everything needed to check story conditions against the player as they are right now
+#[derive(SystemParam)]
+pub struct StoryState<'w, 's> {
+    flags: ResMut<'w, StoryFlags>,
+    standings: Res<'w, FactionStandings>,
+    depth: Res<'w, CurrentDepth>,
+    player_query: Query<'w, 's, StoryPlayerQuery<'static>, With<Player>>,
+    item_query: Query<'w, 's, (&'static Item, &'static EntityName)>,
+}
+
+impl<'w, 's> StoryState<'w, 's> {
+    pub fn player(&self) -> Option<Entity> {
+        self.player_query
+            .get_single()
+            .ok()
+            .map(|(player, _, _)| player)
+    }
+
+    /// `None` if there's no player to check anything against
+    pub fn context(&self) -> Option<StoryContext> {
+        let (_, stats, inventory) = self.player_query.get_single().ok()?;
+        Some(StoryContext::new(
+            stats.copied(),
+            inventory,
+            &self.item_query,
+            &*self.flags,
+            &*self.standings,
+            self.depth.0,
+        ))
+    }
+
+    pub fn set_flag(&mut self, flag: &str) {
+        self.flags.set(flag);
+    }
+}
+
+pub fn update_story_flags(events: Res<CallbackEvents>, mut flags: ResMut<StoryFlags>) {
+    for event in events.iter::<StoryFlagChanged>() {
+        if event.set {
+            flags.set(&event.flag);
+        } else {
+            flags.clear(&event.flag);
+        }
+    }
+
+    for event in events.iter::<StoryCounterChanged>() {
+        flags.add(&event.counter, event.amount);
+    }
+}
+
+/// Hands out quests once they're available, and moves the ones already going along a stage at a
+/// time. Kills and landmarks only count if they happen while the stage is going. A kill is the
+/// player's however they managed it: a blow, an item, or poison they left behind.
+pub fn update_quests(
+    mut events: ResMut<CallbackEvents>,
+    registry: Res<QuestRegistry>,
+    mut quest_log: ResMut<QuestLog>,
+    mut story: StoryState,
+    name_query: Query<&EntityName>,
+    landmark_query: Query<&EntityName, With<Landmark>>,
+) {
+    let player = match story.player() {
+        Some(player) => player,
+        None => return,
+    };
+
+    let kills: Vec<&str> = events
+        .iter::<EntityDies>()
+        .filter(|e| e.killer == Some(player))
+        .filter_map(|e| name_query.get(e.entity).ok())
+        .map(|name| name.0.as_str())
+        .collect();
+    let landmarks: Vec<&str> = events
+        .iter::<EntityFirstSeen>()
+        .filter_map(|e| landmark_query.get(e.entity).ok())
+        .map(|name| name.0.as_str())
+        .collect();
+
+    let context = match story.context() {
+        Some(context) => context,
+        None => return,
+    };
+
+    // worked out first and applied after, so the log is only touched when something changed
+    let mut updated: Vec<(usize, QuestProgress)> = Vec::new();
+    let mut new_flags: Vec<String> = Vec::new();
+    let mut logs: Vec<String> = Vec::new();
+
+    for (idx, progress) in quest_log.0.iter().enumerate() {
+        let def = match registry.get(&progress.name) {
+            Some(def) => def,
+            None => continue,
+        };
+        let stage = match def.stages.get(progress.stage) {
+            Some(stage) => stage,
+            None => continue,
+        };
+
+        let mut next = progress.clone();
+        next.counts.resize(stage.conditions.len(), 0);
+        for (condition, count) in stage.conditions.iter().zip(next.counts.iter_mut()) {
+            *count += match condition {
+                QuestCondition::Kill { name, .. } => kills
+                    .iter()
+                    .filter(|k| k.starts_with(name.as_str()))
+                    .count() as u32,
+                QuestCondition::SeeLandmark(name) => {
+                    landmarks.iter().filter(|l| **l == name.as_str()).count() as u32
+                }
+                QuestCondition::Holds(_) => 0,
+            };
+        }
+
+        let finished =
+            stage.conditions.iter().zip(next.counts.iter()).all(
+                |(condition, count)| match condition {
+                    QuestCondition::Kill { count: needed, .. } => count >= needed,
+                    QuestCondition::SeeLandmark(_) => *count > 0,
+                    QuestCondition::Holds(c) => context.holds(c),
+                },
+            );
+
+        if finished {
+            new_flags.extend(stage.sets_flags.iter().cloned());
+            next.stage += 1;
+            next.counts.clear();
+            match def.stages.get(next.stage) {
+                Some(stage) => {
+                    logs.push(format!("Quest updated: {}. {}", def.name, stage.objective))
+                }
+                None => logs.push(format!("Quest complete: {}.", def.name)),
+            }
+        }
+
+        if next != *progress {
+            updated.push((idx, next));
+        }
+    }
+
+    let started: Vec<&QuestDef> = registry
+        .quests
+        .iter()
+        .filter(|def| quest_log.get(&def.name).is_none())
+        .filter(|def| context.all_hold(&def.starts_when))
+        .collect();
+    for def in started.iter() {
+        logs.push(format!(
+            "New quest: {}. {}",
+            def.name, def.stages[0].objective
+        ));
+    }
+
+    for (idx, progress) in updated {
+        quest_log.0[idx] = progress;
+    }
+    if !started.is_empty() {
+        quest_log
+            .0
+            .extend(started.into_iter().map(|def| QuestProgress {
+                name: def.name.clone(),
+                stage: 0,
+                counts: Vec::new(),
+            }));
+    }
+    for flag in new_flags {
+        story.set_flag(&flag);
+    }
+
+    for message in logs {
+        events.send(LogIssuedEvent {
+            log: Log { message },
+        });
+    }
+}
+
+/// The first ending whose conditions all hold is how the game ends. Dying gets there first.
+pub fn check_endings(
+    events: Res<CallbackEvents>,
+    registry: Res<EndingRegistry>,
+    story: StoryState,
+    mut game_over: GameOverTrigger,
+) {
+    let player = match story.player() {
+        Some(player) => player,
+        None => return,
+    };
+    if events.iter::<EntityDies>().any(|e| e.entity == player) {
+        return;
+    }
+
+    let context = match story.context() {
+        Some(context) => context,
+        None => return,
+    };
+    let ending = match registry
+        .endings
+        .iter()
+        .find(|ending| context.all_hold(&ending.conditions))
+    {
+        Some(ending) => ending,
+        None => return,
+    };
+
+    game_over.end_game(ending.text.clone(), Some(ending.name.clone()));
+}
@@ -0,0 +1,140 @@
+//! Picking out a distant tile: the cursor the player moves around, and whether anything is in the
+//! way of whatever is being aimed there (or, for travel, whether there's anywhere to go).
+
+use bevy::prelude::*;
+
+use crate::components::*;
+use crate::map::Map;
+use crate::resources::*;
+
+/// The tiles on a straight line between two points, not including the start but including the end
+pub fn line_between(from: WorldPos, to: WorldPos) -> Vec<WorldPos> {
+    let dx = (to.x - from.x).abs();
+    let dy = -(to.y - from.y).abs();
+    let step_x = if from.x < to.x { 1 } else { -1 };
+    let step_y = if from.y < to.y { 1 } else { -1 };
+
+    let mut out = Vec::new();
+    let mut current = from;
+    let mut err = dx + dy;
+
+    while current != to {
+        let doubled = 2 * err;
+        if doubled >= dy {
+            err += dy;
+            current.x += step_x;
+        }
+        if doubled <= dx {
+            err += dx;
+            current.y += step_y;
+        }
+        out.push(current);
+    }
+
+    out
+}
+
+/// Whether something can be thrown or shot from one tile to another; anything that would block
+/// the view blocks the shot, including at the target itself.
+pub fn has_line_of_fire(map: &Map, from: WorldPos, to: WorldPos) -> bool {
+    line_between(from, to)
+        .into_iter()
+        .all(|wp| !map.get_tile(wp).blocks_visibility())
+}
+
+/// Whether the cursor is somewhere it can be confirmed: with a clear line of fire for aiming (and
+/// not at the player themselves), or on a seen, walkable tile for travel
+pub fn cursor_is_valid(map: &Map, player_pos: WorldPos, cursor: &TargetingCursor) -> bool {
+    match cursor.purpose {
+        TargetPurpose::UseItem { .. } => {
+            cursor.pos != player_pos && has_line_of_fire(map, player_pos, cursor.pos)
+        }
+        TargetPurpose::Travel => map.is_seen(cursor.pos) && map.passable(cursor.pos),
+    }
+}
+
+/// Input while the targeting cursor is up. When aiming, the cursor can only go to tiles the
+/// player can see, and can only be confirmed with a clear line of fire. When picking somewhere to
+/// travel, anywhere the player has seen will do.
+pub fn handle_targeting_input(
+    entity: Entity,
+    player_pos: WorldPos,
+    viewshed: Option<&Viewshed>,
+    input: &PlayerInputState,
+    map: &Map,
+    targeting: &mut TargetingMode,
+    events: &mut CallbackEvents,
+) {
+    let cursor = match targeting.0.as_mut() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    let log = |events: &mut CallbackEvents, message: &str| {
+        events.send(LogIssuedEvent {
+            log: Log {
+                message: message.to_string(),
+            },
+        });
+    };
+
+    if input.cancel_pressed {
+        targeting.0 = None;
+        log(events, "Never mind.");
+        return;
+    }
+
+    if input.confirm_pressed {
+        if !cursor_is_valid(map, player_pos, cursor) {
+            let complaint = match cursor.purpose {
+                TargetPurpose::UseItem { .. } if cursor.pos == player_pos => {
+                    "You'd rather not aim that at yourself."
+                }
+                TargetPurpose::UseItem { .. } => "There's something in the way.",
+                TargetPurpose::Travel => "You don't know of a way there.",
+            };
+            log(events, complaint);
+            return;
+        }
+
+        match cursor.purpose {
+            TargetPurpose::UseItem { item } => events.send(EntityUsesItem {
+                entity,
+                item,
+                target: Some(cursor.pos),
+            }),
+            TargetPurpose::Travel => events.send(PlayerStartsAutoMove {
+                kind: AutoMoveKind::Travel {
+                    destination: cursor.pos,
+                },
+            }),
+        }
+        targeting.0 = None;
+        return;
+    }
+
+    let mut new_pos = cursor.pos;
+    if input.left_pressed {
+        new_pos.x -= 1;
+    }
+    if input.right_pressed {
+        new_pos.x += 1;
+    }
+    if input.up_pressed {
+        new_pos.y += 1;
+    }
+    if input.down_pressed {
+        new_pos.y -= 1;
+    }
+
+    let allowed = match cursor.purpose {
+        TargetPurpose::UseItem { .. } => {
+            viewshed.map_or(false, |vs| vs.visible_tiles.contains(&new_pos))
+                && map.step_distance(new_pos, player_pos) <= cursor.range
+        }
+        TargetPurpose::Travel => map.is_seen(new_pos),
+    };
+    if allowed {
+        cursor.pos = new_pos;
+    }
+}
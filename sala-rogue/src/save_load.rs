@@ -0,0 +1,517 @@
+//! Saving and loading the whole game to a RON file, and throwing it away to start a new one.
+//!
+//! Only the "real" game state is saved: the map, the logs, the turn order and the components on
+//! the entities that take part in the game. Everything derived from those (the blocked and combat
+//! tile caches, the player distance map, sprites) is rebuilt after a load.
+
+use std::collections::HashMap;
+
+use bevy::ecs::query::{FilterFetch, WorldQuery};
+use bevy::ecs::system::System;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::components::*;
+use crate::levels::StashedLevels;
+use crate::map::{Map, MapSnapshot};
+use crate::resources::*;
+use crate::setup_systems;
+
+/// Bump this whenever the format changes in a way old saves can't be read with.
+const SAVE_VERSION: u32 = 14;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SaveGame {
+    version: u32,
+    seed: u64,
+    /// Where `GameRng` picks up from
+    rng_seed: u64,
+    turn_number: usize,
+    depth: u32,
+    map: MapSnapshot,
+    logs: Logs,
+    /// Indices into `entities` along with when each is next ready, starting with whoever's turn
+    /// it is
+    turn_order: Vec<(usize, u64)>,
+    entities: Vec<SavedEntity>,
+    /// Levels the player has been to, but isn't on right now
+    stashed_levels: Vec<SavedLevel>,
+    story_flags: StoryFlags,
+    quest_log: QuestLog,
+    faction_standings: FactionStandings,
+}
+
+/// A level the player isn't on; everything on it is frozen until they come back.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedLevel {
+    pub depth: u32,
+    pub map: MapSnapshot,
+    pub entities: Vec<SavedEntity>,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedEntity {
+    name: Option<String>,
+    world_pos: Option<WorldPos>,
+    combat_stats: Option<CombatStats>,
+    viewshed: Option<SavedViewshed>,
+    renderable: Option<Renderable>,
+    item: Option<Item>,
+    consumable: Option<Consumable>,
+    equippable: Option<Equippable>,
+    equipped: bool,
+    /// Carried things are saved along with whoever carries them
+    inventory: Option<Vec<SavedEntity>>,
+    player: bool,
+    monster_ai: bool,
+    talker: Option<Talker>,
+    awareness: Option<Awareness>,
+    ai_behaviors: Option<AiBehaviors>,
+    speed: Option<Speed>,
+    faction: Option<Faction>,
+    regard: Option<Regard>,
+    status_effects: Option<StatusEffects>,
+    inflicts_on_hit: Option<InflictsOnHit>,
+    blocks_movement: bool,
+    requires_seen: bool,
+    landmark: Option<Landmark>,
+    seen: bool,
+    touched: bool,
+    end_of_turn_trigger: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SavedViewshed {
+    range: i32,
+    visible_tiles: Vec<WorldPos>,
+}
+
+/// Does whatever save, load or restart has been asked for. This is exclusive so it can happen all
+/// at once, in between turns. Loading or starting a game puts us (back) in the game.
+pub fn process_save_load(world: &mut World) {
+    let action = match world
+        .get_resource_mut::<PendingSaveLoad>()
+        .and_then(|mut pending| pending.0.take())
+    {
+        Some(action) => action,
+        None => return,
+    };
+
+    let path = world
+        .get_resource::<SaveFilePath>()
+        .expect("Save file path should be set up")
+        .0
+        .clone();
+
+    let message = match action {
+        SaveLoadAction::Save => {
+            let save = make_save(world);
+            let result = ron::ser::to_string_pretty(&save, Default::default())
+                .map_err(|e| e.to_string())
+                .and_then(|text| std::fs::write(&path, text).map_err(|e| e.to_string()));
+
+            match result {
+                Ok(()) => format!("Game saved to {}", path.display()),
+                Err(e) => format!("Could not save game to {}: {}", path.display(), e),
+            }
+        }
+        SaveLoadAction::Load => {
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| ron::from_str::<SaveGame>(&text).map_err(|e| e.to_string()))
+                .and_then(|save| {
+                    if save.version == SAVE_VERSION {
+                        Ok(save)
+                    } else {
+                        Err(format!(
+                            "save is version {}, but this game reads version {}",
+                            save.version, SAVE_VERSION
+                        ))
+                    }
+                });
+
+            match result {
+                Ok(save) => {
+                    apply_save(world, save);
+                    enter_game(world);
+                    format!("Game loaded from {}", path.display())
+                }
+                Err(e) => format!("Could not load game from {}: {}", path.display(), e),
+            }
+        }
+        SaveLoadAction::NewGame => {
+            start_new_game(world);
+            enter_game(world);
+            "A new game begins.".to_string()
+        }
+    };
+
+    bevy::log::info!("{}", message);
+    world
+        .get_resource_mut::<CallbackEvents>()
+        .expect("Events should be set up")
+        .send(LogIssuedEvent {
+            log: Log { message },
+        });
+}
+
+/// Everything that's part of the game (as opposed to UI, cameras, visual tiles and so on)
+type SavedEntityFilter = (
+    Or<(With<WorldPos>, With<EndOfTurnTrigger>, With<Landmark>)>,
+    Without<VisualTile>,
+);
+
+/// Snapshot every entity matching the filter, sorted by entity id
+pub fn save_entities<F: WorldQuery>(world: &mut World) -> Vec<(Entity, SavedEntity)>
+where
+    F::Fetch: FilterFetch,
+{
+    let mut entities: Vec<Entity> = world.query_filtered::<Entity, F>().iter(world).collect();
+    entities.sort_by_key(|e| e.id());
+
+    entities
+        .into_iter()
+        .map(|entity| (entity, save_entity(world, entity)))
+        .collect()
+}
+
+/// Snapshot a single entity, including anything it's carrying
+fn save_entity(world: &World, entity: Entity) -> SavedEntity {
+    let viewshed = world.get::<Viewshed>(entity).map(|vs| {
+        let mut visible_tiles: Vec<WorldPos> = vs.visible_tiles.iter().copied().collect();
+        visible_tiles.sort_by_key(|wp| (wp.y, wp.x));
+        SavedViewshed {
+            range: vs.range,
+            visible_tiles,
+        }
+    });
+
+    let inventory = world.get::<Inventory>(entity).map(|inv| {
+        inv.items
+            .iter()
+            .map(|item| save_entity(world, *item))
+            .collect()
+    });
+
+    SavedEntity {
+        name: world.get::<EntityName>(entity).map(|n| n.0.clone()),
+        world_pos: world.get::<WorldPos>(entity).copied(),
+        combat_stats: world.get::<CombatStats>(entity).copied(),
+        viewshed,
+        renderable: world.get::<Renderable>(entity).copied(),
+        item: world.get::<Item>(entity).copied(),
+        consumable: world.get::<Consumable>(entity).cloned(),
+        equippable: world.get::<Equippable>(entity).copied(),
+        equipped: world.get::<Equipped>(entity).is_some(),
+        inventory,
+        player: world.get::<Player>(entity).is_some(),
+        monster_ai: world.get::<MonsterAI>(entity).is_some(),
+        talker: world.get::<Talker>(entity).cloned(),
+        awareness: world.get::<Awareness>(entity).copied(),
+        ai_behaviors: world.get::<AiBehaviors>(entity).cloned(),
+        speed: world.get::<Speed>(entity).copied(),
+        faction: world.get::<Faction>(entity).cloned(),
+        regard: world.get::<Regard>(entity).copied(),
+        status_effects: world.get::<StatusEffects>(entity).cloned(),
+        inflicts_on_hit: world.get::<InflictsOnHit>(entity).cloned(),
+        blocks_movement: world.get::<BlocksMovement>(entity).is_some(),
+        requires_seen: world.get::<RequiresSeen>(entity).is_some(),
+        landmark: world.get::<Landmark>(entity).cloned(),
+        seen: world.get::<Seen>(entity).is_some(),
+        touched: world.get::<Touched>(entity).is_some(),
+        end_of_turn_trigger: world.get::<EndOfTurnTrigger>(entity).is_some(),
+    }
+}
+
+/// Despawn an entity along with anything it's carrying
+pub fn despawn_saved_entity(world: &mut World, entity: Entity) {
+    let carried = world
+        .get::<Inventory>(entity)
+        .map(|inv| inv.items.clone())
+        .unwrap_or_default();
+    for item in carried {
+        despawn_saved_entity(world, item);
+    }
+
+    world.despawn(entity);
+}
+
+/// Spawn a fresh entity from a snapshot. Anything with a position will get indexed into the
+/// tile maps by the usual systems; turn order is up to the caller.
+pub fn spawn_saved_entity(world: &mut World, saved: SavedEntity) -> Entity {
+    // carried things first, so there's something to put in the inventory
+    let inventory = saved.inventory.map(|items| Inventory {
+        items: items
+            .into_iter()
+            .map(|item| spawn_saved_entity(world, item))
+            .collect(),
+    });
+
+    let mut e = world.spawn();
+
+    if let Some(name) = saved.name {
+        e.insert(EntityName(name));
+    }
+    if let Some(wp) = saved.world_pos {
+        e.insert(wp).insert(WantsMapIndexing);
+    }
+    if let Some(cs) = saved.combat_stats {
+        e.insert(cs);
+    }
+    if let Some(vs) = saved.viewshed {
+        e.insert(Viewshed {
+            visible_tiles: vs.visible_tiles.into_iter().collect(),
+            range: vs.range,
+        });
+    }
+    if let Some(renderable) = saved.renderable {
+        e.insert(renderable);
+    }
+    if let Some(item) = saved.item {
+        e.insert(item);
+    }
+    if let Some(consumable) = saved.consumable {
+        e.insert(consumable);
+    }
+    if let Some(equippable) = saved.equippable {
+        e.insert(equippable);
+    }
+    if saved.equipped {
+        e.insert(Equipped);
+    }
+    if let Some(inventory) = inventory {
+        e.insert(inventory);
+    }
+    if saved.player {
+        e.insert(Player);
+    }
+    if saved.monster_ai {
+        e.insert(MonsterAI);
+    }
+    if let Some(talker) = saved.talker {
+        e.insert(talker);
+    }
+    if let Some(awareness) = saved.awareness {
+        e.insert(awareness);
+    }
+    if let Some(ai_behaviors) = saved.ai_behaviors {
+        e.insert(ai_behaviors);
+    }
+    if let Some(speed) = saved.speed {
+        e.insert(speed);
+    }
+    if let Some(faction) = saved.faction {
+        e.insert(faction);
+    }
+    if let Some(regard) = saved.regard {
+        e.insert(regard);
+    }
+    if let Some(status_effects) = saved.status_effects {
+        e.insert(status_effects);
+    }
+    if let Some(inflicts_on_hit) = saved.inflicts_on_hit {
+        e.insert(inflicts_on_hit);
+    }
+    if saved.blocks_movement {
+        e.insert(BlocksMovement);
+    }
+    if saved.requires_seen {
+        e.insert(RequiresSeen);
+    }
+    if let Some(landmark) = saved.landmark {
+        e.insert(landmark);
+    }
+    if saved.seen {
+        e.insert(Seen);
+    }
+    if saved.touched {
+        e.insert(Touched);
+    }
+    if saved.end_of_turn_trigger {
+        e.insert(EndOfTurnTrigger);
+    }
+
+    e.id()
+}
+
+fn make_save(world: &mut World) -> SaveGame {
+    let turn_order: Vec<(Entity, u64)> = world
+        .get_resource::<TurnOrder>()
+        .expect("Turn order should be set up")
+        .iter()
+        .collect();
+
+    let mut found = save_entities::<SavedEntityFilter>(world);
+
+    // turn order first (in order), then everyone else, so the file comes out the same every time
+    let turn_position = |e: Entity| turn_order.iter().position(|(t, _)| *t == e);
+    found.sort_by_key(|(e, _)| (turn_position(*e).unwrap_or(usize::MAX), e.id()));
+
+    let index_of: HashMap<Entity, usize> = found
+        .iter()
+        .enumerate()
+        .map(|(i, (e, _))| (*e, i))
+        .collect();
+
+    let mut stashed_levels: Vec<SavedLevel> = world
+        .get_resource::<StashedLevels>()
+        .map(|stashed| stashed.0.values().cloned().collect())
+        .unwrap_or_default();
+    stashed_levels.sort_by_key(|level| level.depth);
+
+    // the game carries on from a fresh seed too, so it goes the same way as a load of this save
+    let rng_seed: u64 = world
+        .get_resource_mut::<GameRng>()
+        .map(|mut rng| rng.0.gen())
+        .unwrap_or(0);
+    world.insert_resource(GameRng::from_seed(rng_seed));
+
+    SaveGame {
+        version: SAVE_VERSION,
+        seed: world.get_resource::<GameSeed>().map(|s| s.0).unwrap_or(0),
+        rng_seed,
+        turn_number: world
+            .get_resource::<CurrentTurnNumber>()
+            .map(|t| t.0)
+            .unwrap_or(0),
+        depth: world
+            .get_resource::<CurrentDepth>()
+            .map(|d| d.0)
+            .unwrap_or(1),
+        map: world
+            .get_resource::<Map>()
+            .expect("Map should be set up")
+            .snapshot(),
+        logs: world
+            .get_resource::<Logs>()
+            .expect("Logs should be set up")
+            .clone(),
+        turn_order: turn_order
+            .iter()
+            .filter_map(|(e, ready_at)| index_of.get(e).map(|idx| (*idx, *ready_at)))
+            .collect(),
+        entities: found.into_iter().map(|(_, saved)| saved).collect(),
+        stashed_levels,
+        story_flags: world
+            .get_resource::<StoryFlags>()
+            .cloned()
+            .unwrap_or_default(),
+        quest_log: world
+            .get_resource::<QuestLog>()
+            .cloned()
+            .unwrap_or_default(),
+        faction_standings: world
+            .get_resource::<FactionStandings>()
+            .cloned()
+            .unwrap_or_default(),
+    }
+}
+
+/// Get rid of everything belonging to the current game, including anything half-done
+fn clear_game(world: &mut World) {
+    let old: Vec<Entity> = world
+        .query_filtered::<Entity, SavedEntityFilter>()
+        .iter(world)
+        .collect();
+    for entity in old {
+        despawn_saved_entity(world, entity);
+    }
+
+    // anything in flight belongs to the old game
+    world
+        .get_resource_mut::<CallbackEvents>()
+        .expect("Events should be set up")
+        .clear();
+
+    world.remove_resource::<GameOverInfo>();
+    world.insert_resource(PendingLevelChange::default());
+    world.insert_resource(TargetingMode::default());
+    world.insert_resource(AutoMove::default());
+    world.insert_resource(ActiveDialogue::default());
+    world.insert_resource(BlockedTiles::default());
+    world.insert_resource(CombatStatsTiles::default());
+}
+
+/// Switch to playing, from whatever screen we're on
+fn enter_game(world: &mut World) {
+    if let Some(mut state) = world.get_resource_mut::<State<GameState>>() {
+        // already in the game is fine
+        let _ = state.set(GameState::InGame);
+    }
+}
+
+/// Run a system once, right now, as if it were part of a schedule
+fn run_system_once<Params>(world: &mut World, system: impl IntoSystem<(), (), Params>) {
+    let mut system = system.system();
+    system.initialize(world);
+    system.run((), world);
+    system.apply_buffers(world);
+}
+
+/// Throw the current game away and set up a fresh one, just like at startup
+fn start_new_game(world: &mut World) {
+    clear_game(world);
+
+    let seed = world
+        .get_resource::<FixedSeed>()
+        .and_then(|fixed| fixed.0)
+        .unwrap_or_else(rand::random);
+
+    world.insert_resource(GameSeed(seed));
+    world.insert_resource(GameRng::from_seed(seed));
+    world.insert_resource(CurrentTurnNumber::default());
+    world.insert_resource(CurrentDepth(1));
+    world.insert_resource(StashedLevels::default());
+    world.insert_resource(Logs::default());
+    world.insert_resource(StoryFlags::default());
+    world.insert_resource(QuestLog::default());
+    world.insert_resource(FactionStandings::default());
+    world.insert_resource(TurnOrder::default());
+    world.insert_resource(DijkstraMaps::default());
+
+    run_system_once(world, setup_systems::make_map);
+    run_system_once(world, setup_systems::setup_turn_counter);
+}
+
+fn apply_save(world: &mut World, save: SaveGame) {
+    // out with the old
+    clear_game(world);
+
+    // in with the new
+    let spawned: Vec<Entity> = save
+        .entities
+        .into_iter()
+        .map(|saved| spawn_saved_entity(world, saved))
+        .collect();
+
+    let mut turn_order = TurnOrder::default();
+    for (idx, ready_at) in save.turn_order {
+        if let Some(entity) = spawned.get(idx) {
+            turn_order.add_at(*entity, ready_at);
+        }
+    }
+
+    let map = Map::from_snapshot(save.map);
+
+    let stashed_levels = StashedLevels(
+        save.stashed_levels
+            .into_iter()
+            .map(|level| (level.depth, level))
+            .collect(),
+    );
+
+    world.insert_resource(GameSeed(save.seed));
+    world.insert_resource(GameRng::from_seed(save.rng_seed));
+    world.insert_resource(CurrentTurnNumber(save.turn_number));
+    world.insert_resource(CurrentDepth(save.depth));
+    world.insert_resource(map);
+    world.insert_resource(stashed_levels);
+    world.insert_resource(save.logs);
+    world.insert_resource(save.story_flags);
+    world.insert_resource(save.quest_log);
+    world.insert_resource(save.faction_standings);
+    world.insert_resource(turn_order);
+    world.insert_resource(DijkstraMaps::default());
+}
@@ -1,21 +1,52 @@
+use std::collections::HashSet;
+
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 
-use crate::bevy_util::make_basic_sprite_bundle;
 use crate::components::*;
 use crate::map::*;
+use crate::map_builders::{make_builder, BuiltLevel};
+use crate::raws::{ItemRegistry, LevelStyleRegistry, MonsterRegistry, NpcRegistry};
 use crate::resources::*;
+use crate::running_systems::distance_dijkstra_map;
 
-pub fn make_map(
-    mut map_res: ResMut<Map>,
-    mut events: ResMut<CallbackEvents>,
-    mut commands: Commands,
-    sheet: Res<BasicTilesAtlas>,
-) {
-    let (map, rooms) = make_new_map();
+/// Everything `generate_level` needs to build the current level
+#[derive(SystemParam)]
+pub struct LevelGenParams<'w, 's> {
+    seed: Res<'w, GameSeed>,
+    depth: Res<'w, CurrentDepth>,
+    monsters: Res<'w, MonsterRegistry>,
+    items: Res<'w, ItemRegistry>,
+    npcs: Res<'w, NpcRegistry>,
+    level_styles: Res<'w, LevelStyleRegistry>,
+    #[system_param(ignore)]
+    _marker: std::marker::PhantomData<&'s ()>,
+}
+
+impl<'w, 's> LevelGenParams<'w, 's> {
+    pub fn seed(&self) -> u64 {
+        self.seed.0
+    }
+
+    /// See `generate_level`
+    pub fn generate(&self, commands: &mut Commands) -> (Map, WorldPos) {
+        generate_level(
+            commands,
+            &*self.monsters,
+            &*self.items,
+            &*self.npcs,
+            &*self.level_styles,
+            self.seed.0,
+            self.depth.0,
+        )
+    }
+}
 
-    let room = rooms[0];
-    let (x, y) = room.center();
+pub fn make_map(mut events: ResMut<CallbackEvents>, mut commands: Commands, level: LevelGenParams) {
+    let (map, WorldPos { x, y }) = level.generate(&mut commands);
 
     commands
         .spawn()
@@ -27,75 +58,195 @@ pub fn make_map(
             power: 5,
         })
         .insert(EntityName("Player".to_string()))
-        .insert_bundle(make_basic_sprite_bundle(2, &sheet.0, Color::ALICE_BLUE))
+        // TODO: coherent layering management system, not like this
+        .insert(Renderable {
+            sprite_index: 2,
+            color: Color::ALICE_BLUE,
+            layer: 100.0,
+        })
         .insert(Viewshed::new())
+        .insert(Speed::NORMAL)
+        .insert(StatusEffects::default())
+        .insert(Inventory::default())
         .insert(RequiresSeen)
         .insert(WantsTurnOrderAssignment)
         .insert(WantsMapIndexing)
         .insert(BlocksMovement)
-        .insert(WorldPos { x, y })
-        // TODO: coherent layering management system, not like this
-        .insert(Transform::from_xyz(0.0, 0.0, 100.0));
+        .insert(WorldPos { x, y });
 
-    let mut rng = rand::thread_rng();
+    commands.insert_resource(map);
 
-    let make_sprite = |kind| match kind {
-        MonsterKind::KnifeOrc => make_basic_sprite_bundle(0, &sheet.0, Color::LIME_GREEN),
-        MonsterKind::StrongOrc => make_basic_sprite_bundle(33, &sheet.0, Color::ORANGE_RED),
-    };
+    events.send(MapChangedEvent);
+    events.send(LogIssuedEvent {
+        log: Log {
+            message: format!("Dungeon seed: {}", level.seed()),
+        },
+    });
+}
+
+/// Odds that a spawn region gets an item lying around in it
+const ITEM_CHANCE: f64 = 0.5;
+
+/// Each level gets its own seed, so levels don't depend on the order they're visited in
+fn level_seed(game_seed: u64, depth: u32) -> u64 {
+    game_seed ^ (depth as u64 - 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+/// Build a brand new level at the given depth and spawn its monsters and items. The layout comes from
+/// whichever builder the level styles pick; the up stairs (if any) are where the builder says the
+/// player starts, and the down stairs are as far from there as possible.
+///
+/// Returns the map and that start position.
+pub fn generate_level(
+    commands: &mut Commands,
+    monsters: &MonsterRegistry,
+    items: &ItemRegistry,
+    npcs: &NpcRegistry,
+    level_styles: &LevelStyleRegistry,
+    game_seed: u64,
+    depth: u32,
+) -> (Map, WorldPos) {
+    // everything random about the level has to come out of this, or the seed is worthless
+    let mut rng = StdRng::seed_from_u64(level_seed(game_seed, depth));
+
+    let (style, with_vaults) = level_styles.pick(depth, &mut rng);
+    let BuiltLevel {
+        mut map,
+        player_start,
+        spawn_regions,
+        tagged_rooms,
+    } = make_builder(style, with_vaults).build(&mut rng);
+
+    // ties broken by position, so the same seed always puts the stairs in the same place
+    let distances = distance_dijkstra_map(&map, [player_start].iter(), |_| false);
+    let farthest = distances
+        .iter()
+        .max_by_key(|(wp, dist)| (**dist, -wp.y, -wp.x))
+        .map(|(wp, _)| *wp)
+        .filter(|wp| *wp != player_start);
+
+    if depth > 1 {
+        map.set_tile(player_start, TileType::UpStairs);
+    }
+
+    if let Some(wp) = farthest {
+        map.set_tile(wp, TileType::DownStairs);
+    }
+
+    for room in tagged_rooms {
+        commands
+            .spawn()
+            .insert(EntityName(room.name.to_string()))
+            .insert(Landmark {
+                description: room.description.to_string(),
+                tiles: room.tiles,
+            });
+    }
+
+    let monster_table = monsters.spawn_table(depth);
+    let item_table = items.spawn_table(depth);
+
+    let mut monster_spots = Vec::new();
+    for (idx, region) in spawn_regions.iter().enumerate() {
+        let mut spots: Vec<WorldPos> = region
+            .iter()
+            .copied()
+            .filter(|wp| map.get_tile(*wp) == TileType::Floor)
+            .collect();
 
-    let mut idx = 0;
-    let mut make_name = |kind| {
-        let this_idx = idx;
-        idx += 1;
-        match kind {
-            MonsterKind::KnifeOrc => EntityName(format!("Knife-wielding orc #{}", this_idx)),
-            MonsterKind::StrongOrc => EntityName(format!("Orc #{}", this_idx)),
+        if let Some(table) = monster_table.as_ref().filter(|_| !spots.is_empty()) {
+            let wp = spots.swap_remove(rng.gen_range(0..spots.len()));
+            let def = table.roll(&mut rng);
+            monster_spots.push(wp);
+
+            let mut monster = commands.spawn();
+            monster
+                .insert(def.make_viewshed())
+                .insert(wp)
+                .insert(RequiresSeen)
+                .insert(MonsterAI)
+                .insert(Awareness::default())
+                .insert(def.make_behaviors(wp))
+                .insert(def.make_speed())
+                .insert(def.make_faction())
+                .insert(Regard::default())
+                .insert(StatusEffects::default())
+                .insert(BlocksMovement)
+                .insert(WantsTurnOrderAssignment)
+                .insert(WantsMapIndexing)
+                .insert(def.make_renderable())
+                .insert(def.make_name(idx))
+                .insert(def.make_stats());
+            if let Some(on_hit) = def.make_on_hit() {
+                monster.insert(on_hit);
+            }
         }
-    };
 
-    let make_stats = |kind| match kind {
-        MonsterKind::KnifeOrc => CombatStats {
-            max_hp: 12,
-            hp: 12,
-            defense: 1,
-            power: 4,
-        },
-        MonsterKind::StrongOrc => CombatStats {
-            max_hp: 16,
-            hp: 16,
-            defense: 2,
-            power: 3,
-        },
-    };
+        if let Some(table) = item_table.as_ref().filter(|_| !spots.is_empty()) {
+            if rng.gen_bool(ITEM_CHANCE) {
+                let wp = spots[rng.gen_range(0..spots.len())];
+                let def = table.roll(&mut rng);
 
-    for room in rooms.iter().skip(1) {
-        let (x, y) = room.center();
+                let mut item = commands.spawn();
+                item.insert(wp)
+                    .insert(RequiresSeen)
+                    .insert(def.make_item(&mut rng))
+                    .insert(def.make_renderable())
+                    .insert(def.make_name());
+                if let Some(consumable) = def.consumable.clone() {
+                    item.insert(consumable);
+                }
+                if let Some(equippable) = def.equippable {
+                    item.insert(equippable);
+                }
+            }
+        }
+    }
 
-        let kind = match rng.gen_range(0..2) {
-            0 => MonsterKind::KnifeOrc,
-            1 => MonsterKind::StrongOrc,
-            _ => unreachable!(),
+    // NPCs go last, so they don't shift around anything else the seed decides
+    let mut occupied: HashSet<WorldPos> = monster_spots.into_iter().chain([player_start]).collect();
+    for def in npcs.on_depth(depth) {
+        let spots: Vec<WorldPos> = spawn_regions
+            .iter()
+            .flat_map(|region| region.iter().copied())
+            .filter(|wp| map.get_tile(*wp) == TileType::Floor && !occupied.contains(wp))
+            .collect();
+        let wp = match spots.choose(&mut rng) {
+            Some(wp) => *wp,
+            None => continue,
         };
+        occupied.insert(wp);
 
         commands
             .spawn()
-            .insert(Viewshed::new())
-            .insert(WorldPos { x, y })
+            .insert(def.make_viewshed())
+            .insert(wp)
             .insert(RequiresSeen)
-            .insert(MonsterAI)
+            .insert(def.make_talker())
+            .insert(def.make_faction())
+            .insert(Regard::default())
+            .insert(StatusEffects::default())
             .insert(BlocksMovement)
-            .insert(WantsTurnOrderAssignment)
             .insert(WantsMapIndexing)
-            .insert_bundle(make_sprite(kind))
-            .insert(make_name(kind))
-            .insert(make_stats(kind))
-            .insert(Transform::from_xyz(0.0, 0.0, 40.0));
+            .insert(def.make_renderable())
+            .insert(def.make_name())
+            .insert(def.make_stats());
     }
 
-    *map_res = map;
+    (map, player_start)
+}
 
-    events.send(MapChangedEvent);
+/// Where the player shows up after taking the stairs in the given direction; that is, on the
+/// opposite stairs. The first level has no up stairs, so it's just somewhere on the floor.
+pub fn arrival_point(map: &Map, direction: StairsDirection) -> WorldPos {
+    let stairs = match direction {
+        StairsDirection::Down => TileType::UpStairs,
+        StairsDirection::Up => TileType::DownStairs,
+    };
+
+    map.find_tile(stairs)
+        .or_else(|| map.find_tile(TileType::Floor))
+        .expect("Every level should have some floor")
 }
 
 pub fn setup_turn_counter(mut commands: Commands) {
@@ -105,12 +256,6 @@ pub fn setup_turn_counter(mut commands: Commands) {
         .insert(WantsTurnOrderAssignment);
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
-enum MonsterKind {
-    StrongOrc,
-    KnifeOrc,
-}
-
 pub fn load_tileset(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -177,6 +322,152 @@ pub fn setup_log_component(mut commands: Commands) {
         });
 }
 
+pub fn setup_inventory_component(mut commands: Commands) {
+    // starts out hidden; see toggle_inventory_panel
+    let hidden = Visibility { is_visible: false };
+
+    // content box on the right side; 30% width and 50% height, below the fps counter
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(30.0), Val::Percent(50.0)),
+                position: Rect {
+                    top: Val::Percent(5.0),
+                    right: Val::Percent(2.5),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            color: Color::rgba(0.1, 0.1, 0.1, 0.8).into(),
+            visibility: hidden.clone(),
+            ..Default::default()
+        })
+        .insert(InventoryPanel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    node: Default::default(),
+                    style: Style {
+                        align_self: AlignSelf::FlexEnd,
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            top: Val::Px(15.0),
+                            left: Val::Px(15.0),
+                            ..Default::default()
+                        },
+                        overflow: Overflow::Hidden,
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![],
+                        alignment: Default::default(),
+                    },
+                    visibility: hidden,
+                    ..Default::default()
+                })
+                .insert(InventoryPanel)
+                .insert(InventoryTextBox);
+        });
+}
+
+pub fn setup_journal_component(mut commands: Commands) {
+    // starts out hidden; see toggle_journal_panel
+    let hidden = Visibility { is_visible: false };
+
+    // content box on the left side, mirroring the inventory
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(30.0), Val::Percent(50.0)),
+                position: Rect {
+                    top: Val::Percent(5.0),
+                    left: Val::Percent(2.5),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            color: Color::rgba(0.1, 0.1, 0.1, 0.8).into(),
+            visibility: hidden.clone(),
+            ..Default::default()
+        })
+        .insert(JournalPanel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    node: Default::default(),
+                    style: Style {
+                        align_self: AlignSelf::FlexEnd,
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            top: Val::Px(15.0),
+                            left: Val::Px(15.0),
+                            ..Default::default()
+                        },
+                        overflow: Overflow::Hidden,
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![],
+                        alignment: Default::default(),
+                    },
+                    visibility: hidden,
+                    ..Default::default()
+                })
+                .insert(JournalPanel)
+                .insert(JournalTextBox);
+        });
+}
+
+pub fn setup_dialogue_component(mut commands: Commands) {
+    // only shown mid-conversation; see update_dialogue_text
+    let hidden = Visibility { is_visible: false };
+
+    // across the bottom of the screen, over the log
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(70.0), Val::Percent(40.0)),
+                position: Rect {
+                    bottom: Val::Percent(5.0),
+                    left: Val::Percent(15.0),
+                    ..Default::default()
+                },
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            color: Color::rgba(0.05, 0.05, 0.1, 0.9).into(),
+            visibility: hidden.clone(),
+            ..Default::default()
+        })
+        .insert(DialoguePanel)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    node: Default::default(),
+                    style: Style {
+                        align_self: AlignSelf::FlexEnd,
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            top: Val::Px(15.0),
+                            left: Val::Px(15.0),
+                            ..Default::default()
+                        },
+                        overflow: Overflow::Hidden,
+                        ..Default::default()
+                    },
+                    text: Text {
+                        sections: vec![],
+                        alignment: Default::default(),
+                    },
+                    visibility: hidden,
+                    ..Default::default()
+                })
+                .insert(DialoguePanel);
+        });
+}
+
 pub fn setup_fps_tracker(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands
         .spawn_bundle(TextBundle {